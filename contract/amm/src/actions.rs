@@ -0,0 +1,229 @@
+use near_sdk::{
+	env,
+	json_types::U128,
+	near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId,
+};
+
+use crate::*;
+
+// Step of a batch run by `execute_actions` against the internal balances of the caller.
+// A missing amount takes the output of the previous swap, so swaps can be chained, or the
+// transferred amount in the first action of an ft_transfer_call.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum Action {
+	Swap {
+		buy_token_id: AccountId,
+		sell_token_id: AccountId,
+		sell_amount: Option<U128>,
+		min_amount_out: Option<U128>,
+		referral_id: Option<AccountId>,
+	},
+	AddLiquidity {
+		token_a_name: AccountId,
+		token_a_amount: U128,
+		token_b_name: AccountId,
+		token_b_amount: U128,
+	},
+	RemoveLiquidity {
+		token_a_name: AccountId,
+		token_b_name: AccountId,
+	},
+	Withdraw {
+		token_id: AccountId,
+		amount: Option<U128>,
+	},
+}
+
+#[near_bindgen]
+impl Contract {
+	// Runs the actions in order and returns the output of every swap, 0 for other actions.
+	// Any error fails the call, which reverts the whole batch.
+	#[handle_result]
+	pub fn execute_actions(
+		&mut self,
+		actions: Vec<Action>,
+		guard: Option<TradeGuard>,
+	) -> Result<Vec<U128>, AmmError> {
+		self.assert_trade_guard(&guard)?;
+		let account_id = env::predecessor_account_id();
+		self.internal_execute_actions(&account_id, actions, None)
+	}
+}
+
+impl Contract {
+	pub(crate) fn internal_execute_actions(
+		&mut self,
+		account_id: &AccountId,
+		actions: Vec<Action>,
+		amount_in: Option<U128>,
+	) -> Result<Vec<U128>, AmmError> {
+		let mut results = Vec::with_capacity(actions.len());
+		let mut last_amount_out = amount_in;
+		for (index, action) in actions.into_iter().enumerate() {
+			let amount_out = match action {
+				Action::Swap {
+					buy_token_id,
+					sell_token_id,
+					sell_amount,
+					min_amount_out,
+					referral_id,
+				} => {
+					let sell_amount = sell_amount
+						.or(last_amount_out)
+						.ok_or(AmmError::MissingActionAmount(index as u32))?;
+					let amount_out = self.internal_swap_tokens(
+						account_id,
+						DEFAULT_POOL_ID,
+						buy_token_id,
+						sell_token_id,
+						sell_amount,
+						referral_id,
+					)?;
+					// A tripped circuit breaker swaps nothing, the rest of the batch can't run
+					if self.is_paused(Some(DEFAULT_POOL_ID), PauseAction::Swaps) {
+						return Err(AmmError::Paused(PauseAction::Swaps))
+					}
+					if amount_out.0 < min_amount_out.map_or(0, |amount| amount.0) {
+						return Err(AmmError::SlippageExceeded)
+					}
+					Some(amount_out)
+				},
+				Action::AddLiquidity {
+					token_a_name,
+					token_a_amount,
+					token_b_name,
+					token_b_amount,
+				} => {
+					self.internal_add_liquidity(
+						account_id,
+						token_a_name,
+						token_a_amount,
+						token_b_name,
+						token_b_amount,
+						None,
+					)?;
+					None
+				},
+				Action::RemoveLiquidity { token_a_name, token_b_name } => {
+					self.internal_remove_liquidity(account_id, token_a_name, token_b_name, None)?;
+					None
+				},
+				Action::Withdraw { token_id, amount } => {
+					let amount = amount
+						.or(last_amount_out)
+						.ok_or(AmmError::MissingActionAmount(index as u32))?;
+					self.internal_withdraw_tokens(account_id, token_id, amount)?;
+					None
+				},
+			};
+			results.push(amount_out.unwrap_or(U128(0)));
+			last_amount_out = amount_out;
+		}
+		Ok(results)
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+	use near_sdk::{test_utils::accounts, testing_env, PromiseOrValue};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	fn swap(
+		buy_token_id: AccountId,
+		sell_token_id: AccountId,
+		sell_amount: Option<u128>,
+	) -> Action {
+		Action::Swap {
+			buy_token_id,
+			sell_token_id,
+			sell_amount: sell_amount.map(U128),
+			min_amount_out: None,
+			referral_id: None,
+		}
+	}
+
+	#[test]
+	fn test_execute_actions() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 0);
+		deposit(&mut contract, &token_b(), &accounts(2), 1_000);
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+
+		// Round trip, the second swap sells the output of the first one
+		let results = contract
+			.execute_actions(
+				vec![swap(token_a(), token_b(), Some(1_000)), swap(token_b(), token_a(), None)],
+				None,
+			)
+			.unwrap();
+		let first = calc_dy(POOL_B, POOL_A, 1_000).unwrap();
+		assert_eq!(results[0], U128(first));
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(0));
+		assert_eq!(contract.ft_balance_of(token_b(), accounts(2)).unwrap(), results[1]);
+
+		assert_eq!(
+			contract.execute_actions(vec![swap(token_a(), token_b(), None)], None),
+			Err(AmmError::MissingActionAmount(0))
+		);
+	}
+
+	#[test]
+	fn test_circuit_breaker_fails_batch() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 0);
+		deposit(&mut contract, &token_b(), &accounts(2), 101_000);
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		contract
+			.set_circuit_breaker(Some(CircuitBreaker {
+				max_price_deviation_bps: 1_000,
+				twap_window_sec: 60,
+			}))
+			.unwrap();
+		let mut pool = contract.get_pool(DEFAULT_POOL_ID).unwrap();
+		pool.price_oracle.record(0, calc_price(POOL_A, POOL_B).unwrap());
+		contract.pools.replace(DEFAULT_POOL_ID, &pool);
+		testing_env!(context
+			.predecessor_account_id(accounts(2))
+			.block_timestamp(60 * NANOS_PER_SECOND)
+			.build());
+
+		// The second swap moves the price too far, so the withdrawal after it never runs
+		let actions = vec![
+			swap(token_a(), token_b(), Some(1_000)),
+			swap(token_a(), token_b(), Some(100_000)),
+			Action::Withdraw { token_id: token_a(), amount: None },
+		];
+		assert_eq!(
+			contract.execute_actions(actions, None),
+			Err(AmmError::Paused(PauseAction::Swaps))
+		);
+		assert!(contract.is_paused(Some(DEFAULT_POOL_ID), PauseAction::Swaps));
+	}
+
+	#[test]
+	fn test_actions_from_transfer() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 0);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+		testing_env!(context.predecessor_account_id(token_b()).build());
+
+		let msg =
+			r#"{"actions": [{"swap": {"buy_token_id": "danny", "sell_token_id": "eugene"}}]}"#;
+		match contract.ft_on_transfer(accounts(2), U128(100), msg.to_string()) {
+			PromiseOrValue::Value(unused) => assert_eq!(unused, U128(0)),
+			PromiseOrValue::Promise(_) => unreachable!(),
+		}
+		assert_eq!(
+			contract.ft_balance_of(token_a(), accounts(2)).unwrap(),
+			U128(calc_dy(POOL_B, POOL_A, 100).unwrap())
+		);
+		assert_eq!(contract.ft_balance_of(token_b(), accounts(2)).unwrap(), U128(0));
+	}
+}
@@ -0,0 +1,426 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	collections::{LookupMap, UnorderedMap},
+	env,
+	json_types::{U128, U64},
+	log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId,
+};
+
+use crate::*;
+
+// Orders of a pool collected over one epoch and settled together at a single price
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Batch {
+	pub batch_id: u64,
+	pub pool_id: u64,
+	// Orders join until the block before end_block, settlement is possible from end_block on
+	pub end_block: U64,
+	// Escrowed amounts selling token_ids[0] and token_ids[1] of the pool
+	pub amounts_in: Vec<U128>,
+	// Amounts paid to the sellers of each token, in the other token
+	pub amounts_out: Vec<U128>,
+	// token_ids[0] priced in token_ids[1] scaled by PRICE_PRECISION, set by the settlement
+	pub clearing_price: Option<U128>,
+	// Settled without a trade, the orders get their escrow back
+	pub refunded: bool,
+	// Escrow of token_ids[0] and token_ids[1] still owed to the orders
+	pub unclaimed: Vec<U128>,
+}
+
+// Blocks after the end of an epoch from which its orders can be cancelled if it isn't settled
+pub const BATCH_SETTLEMENT_BLOCKS: u64 = 1_000;
+
+// Periodic batch auctions, an alternative way to trade a pool. Both sides of a batch are
+// matched against each other at a uniform price and only the imbalance trades against the
+// curve, so the order of the orders within an epoch doesn't matter.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct BatchAuctions {
+	// pool_id:blocks per epoch of the pools with batch auctions
+	pub epoch_blocks: LookupMap<u64, u64>,
+	// batch_id:batch
+	pub batches: UnorderedMap<u64, Batch>,
+	// pool_id:batch_id taking orders
+	pub open_batches: LookupMap<u64, u64>,
+	// (batch_id, account_id):escrowed amounts selling each token
+	pub orders: LookupMap<(u64, AccountId), Vec<U128>>,
+	pub next_batch_id: u64,
+}
+
+impl BatchAuctions {
+	pub fn new() -> Self {
+		Self {
+			epoch_blocks: LookupMap::new(b"bae".to_vec()),
+			batches: UnorderedMap::new(b"bab".to_vec()),
+			open_batches: LookupMap::new(b"bao".to_vec()),
+			orders: LookupMap::new(b"bord".to_vec()),
+			next_batch_id: 0,
+		}
+	}
+}
+
+impl Default for BatchAuctions {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	// Enables batch auctions for the pool with epochs of `epoch_blocks`, or disables new batches
+	#[handle_result]
+	pub fn set_batch_auction(
+		&mut self,
+		pool_id: u64,
+		epoch_blocks: Option<u64>,
+	) -> Result<(), AmmError> {
+		self.assert_owner()?;
+		self.assert_pool_exists(pool_id)?;
+		match epoch_blocks {
+			Some(0) => return Err(AmmError::BatchAuctionDisabled(pool_id)),
+			Some(epoch_blocks) => self.batch_auctions.epoch_blocks.insert(&pool_id, &epoch_blocks),
+			None => self.batch_auctions.epoch_blocks.remove(&pool_id),
+		};
+		Ok(())
+	}
+
+	// Escrows `amount` of sell_token_id from the internal balance of the caller into the batch
+	// of the current epoch and returns its id
+	#[handle_result]
+	pub fn place_batch_order(
+		&mut self,
+		pool_id: u64,
+		sell_token_id: AccountId,
+		amount: U128,
+	) -> Result<u64, AmmError> {
+		self.assert_not_paused(Some(pool_id), PauseAction::Swaps)?;
+		let epoch_blocks = self
+			.batch_auctions
+			.epoch_blocks
+			.get(&pool_id)
+			.ok_or(AmmError::BatchAuctionDisabled(pool_id))?;
+		let index = self.get_pool(pool_id)?.token_index(&sell_token_id)?;
+		if amount.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		let account_id = env::predecessor_account_id();
+		self.internal_escrow(&sell_token_id, &account_id, amount.0)?;
+
+		let block_height = env::block_height();
+		let mut batch = match self.batch_auctions.open_batches.get(&pool_id) {
+			Some(batch_id) => self.get_batch(batch_id)?,
+			None => self.new_batch(pool_id, block_height, epoch_blocks),
+		};
+		if block_height >= batch.end_block.0 {
+			batch = self.new_batch(pool_id, block_height, epoch_blocks);
+		}
+		batch.amounts_in[index].0 += amount.0;
+		batch.unclaimed[index].0 += amount.0;
+		let key = (batch.batch_id, account_id);
+		let mut order = self.batch_auctions.orders.get(&key).unwrap_or_else(|| vec![U128(0); 2]);
+		order[index].0 += amount.0;
+		self.batch_auctions.orders.insert(&key, &order);
+		self.batch_auctions.batches.insert(&batch.batch_id, &batch);
+		self.batch_auctions.open_batches.insert(&pool_id, &batch.batch_id);
+		Ok(batch.batch_id)
+	}
+
+	// Settles an ended batch, callable by anyone. Returns the clearing price, or 0 if the batch was
+	// refunded or the circuit breaker tripped and the batch stays unsettled.
+	#[handle_result]
+	pub fn settle_batch(&mut self, batch_id: u64) -> Result<U128, AmmError> {
+		let mut batch = self.get_batch(batch_id)?;
+		if batch.clearing_price.is_some() {
+			return Err(AmmError::BatchSettled(batch_id))
+		}
+		if env::block_height() < batch.end_block.0 {
+			return Err(AmmError::BatchNotEnded(batch.end_block.0))
+		}
+		self.assert_not_paused(Some(batch.pool_id), PauseAction::Swaps)?;
+		let pool = self.get_pool(batch.pool_id)?;
+		let pool_owner_id = env::current_account_id();
+		let reserve_0 = internal_balance_of(&self.get_token(&pool.token_ids[0])?, &pool_owner_id)?;
+		let reserve_1 = internal_balance_of(&self.get_token(&pool.token_ids[1])?, &pool_owner_id)?;
+		let fee_bps = pool.effective_fee_bps(env::block_timestamp());
+		let (amount_0, amount_1) = (batch.amounts_in[0].0, batch.amounts_in[1].0);
+
+		// The heavier side trades the imbalance against the curve
+		let swap = match clearing_swap_amount(amount_0, amount_1, reserve_0, reserve_1, fee_bps)? {
+			Some(amount_in) => Some((0, amount_in)),
+			None => clearing_swap_amount(amount_1, amount_0, reserve_1, reserve_0, fee_bps)?
+				.map(|amount_in| (1, amount_in)),
+		};
+		let amount_out = match swap {
+			Some((index_in, amount_in)) =>
+				self.quote_swap(
+					batch.pool_id,
+					&pool.token_ids[index_in],
+					&pool.token_ids[1 - index_in],
+					amount_in,
+				)?
+				.0,
+			None => 0,
+		};
+		let (out_0, out_1) = match swap {
+			Some((0, amount_in)) => (amount_1 + amount_out, amount_0 - amount_in),
+			Some((_, amount_in)) => (amount_1 - amount_in, amount_0 + amount_out),
+			// Both sides cross exactly
+			None => (amount_1, amount_0),
+		};
+
+		// A side paid nothing, e.g. a tiny one-sided batch, or a batch whose orders were all
+		// cancelled can't be priced
+		if (out_0 == 0 && amount_1 == 0) || (out_1 == 0 && amount_0 == 0) {
+			batch.clearing_price = Some(U128(0));
+			batch.refunded = true;
+			self.close_batch(&batch);
+			log!("Batch {} refunded", batch_id);
+			return Ok(U128(0))
+		}
+		if let Some((index_in, amount_in)) = swap {
+			let (token_in, token_out) = (&pool.token_ids[index_in], &pool.token_ids[1 - index_in]);
+			if self.internal_swap(batch.pool_id, token_in, token_out, amount_in)?.is_none() {
+				return Ok(U128(0))
+			}
+			self.sub_escrow(token_in, amount_in);
+			self.add_escrow(token_out, amount_out);
+			self.match_limit_orders(batch.pool_id, token_out)?;
+		}
+
+		batch.amounts_out = vec![U128(out_0), U128(out_1)];
+		batch.unclaimed = vec![U128(out_1), U128(out_0)];
+		batch.clearing_price = Some(U128(if amount_0 > 0 {
+			mul_div(out_0, PRICE_PRECISION, amount_0)?
+		} else {
+			mul_div(amount_1, PRICE_PRECISION, out_1)?
+		}));
+		self.close_batch(&batch);
+		log!("Batch {} settled at {}", batch_id, batch.clearing_price.unwrap().0);
+		self.assert_invariants()?;
+		Ok(batch.clearing_price.unwrap())
+	}
+
+	// Credits the caller's share of a settled batch to its internal balances
+	#[handle_result]
+	pub fn claim_batch_order(&mut self, batch_id: u64) -> Result<Vec<TokenAmount>, AmmError> {
+		let mut batch = self.get_batch(batch_id)?;
+		if batch.clearing_price.is_none() {
+			return Err(AmmError::BatchNotSettled(batch_id))
+		}
+		let account_id = env::predecessor_account_id();
+		let order = self
+			.batch_auctions
+			.orders
+			.remove(&(batch_id, account_id.clone()))
+			.ok_or(AmmError::OrderNotFound(batch_id))?;
+		let pool = self.get_pool(batch.pool_id)?;
+		let mut claimed = vec![];
+		for (index, amount) in order.iter().enumerate() {
+			if amount.0 == 0 {
+				continue
+			}
+			// Sellers of one token are paid pro rata in the other one
+			let (paid_index, amount) = if batch.refunded {
+				(index, amount.0)
+			} else {
+				(
+					1 - index,
+					mul_div(amount.0, batch.amounts_out[index].0, batch.amounts_in[index].0)?,
+				)
+			};
+			let token_id = &pool.token_ids[paid_index];
+			batch.unclaimed[paid_index].0 -= amount;
+			self.internal_release(token_id, &account_id, amount)?;
+			claimed.push(TokenAmount { token_id: token_id.clone(), amount: U128(amount) });
+		}
+		self.batch_auctions.batches.insert(&batch_id, &batch);
+		Ok(claimed)
+	}
+
+	// Returns the escrow of the caller's order in a batch which can't be settled: batch auctions
+	// of the pool were disabled, or the batch wasn't settled BATCH_SETTLEMENT_BLOCKS after its end
+	#[handle_result]
+	pub fn cancel_batch_order(&mut self, batch_id: u64) -> Result<Vec<TokenAmount>, AmmError> {
+		let mut batch = self.get_batch(batch_id)?;
+		if batch.clearing_price.is_some() {
+			return Err(AmmError::BatchSettled(batch_id))
+		}
+		let disabled = !self.batch_auctions.epoch_blocks.contains_key(&batch.pool_id);
+		let overdue = env::block_height() >= batch.end_block.0 + BATCH_SETTLEMENT_BLOCKS;
+		if !disabled && !overdue {
+			return Err(AmmError::BatchNotCancellable(batch_id))
+		}
+		let account_id = env::predecessor_account_id();
+		let order = self
+			.batch_auctions
+			.orders
+			.remove(&(batch_id, account_id.clone()))
+			.ok_or(AmmError::OrderNotFound(batch_id))?;
+		let pool = self.get_pool(batch.pool_id)?;
+		let mut refunded = vec![];
+		for (index, amount) in order.iter().enumerate() {
+			if amount.0 == 0 {
+				continue
+			}
+			batch.amounts_in[index].0 -= amount.0;
+			batch.unclaimed[index].0 -= amount.0;
+			self.internal_release(&pool.token_ids[index], &account_id, amount.0)?;
+			refunded.push(TokenAmount { token_id: pool.token_ids[index].clone(), amount: *amount });
+		}
+		self.batch_auctions.batches.insert(&batch_id, &batch);
+		Ok(refunded)
+	}
+
+	#[handle_result]
+	pub fn get_batch(&self, batch_id: u64) -> Result<Batch, AmmError> {
+		self.batch_auctions
+			.batches
+			.get(&batch_id)
+			.ok_or(AmmError::BatchNotFound(batch_id))
+	}
+
+	pub fn get_open_batch(&self, pool_id: u64) -> Option<Batch> {
+		self.batch_auctions
+			.open_batches
+			.get(&pool_id)
+			.and_then(|batch_id| self.batch_auctions.batches.get(&batch_id))
+	}
+
+	pub fn get_batch_order(&self, batch_id: u64, account_id: AccountId) -> Option<Vec<U128>> {
+		self.batch_auctions.orders.get(&(batch_id, account_id))
+	}
+}
+
+impl Contract {
+	// Epochs are aligned to multiples of epoch_blocks
+	fn new_batch(&mut self, pool_id: u64, block_height: u64, epoch_blocks: u64) -> Batch {
+		let batch_id = self.batch_auctions.next_batch_id;
+		self.batch_auctions.next_batch_id += 1;
+		Batch {
+			batch_id,
+			pool_id,
+			end_block: U64((block_height / epoch_blocks + 1) * epoch_blocks),
+			amounts_in: vec![U128(0); 2],
+			amounts_out: vec![U128(0); 2],
+			clearing_price: None,
+			refunded: false,
+			unclaimed: vec![U128(0); 2],
+		}
+	}
+
+	fn close_batch(&mut self, batch: &Batch) {
+		self.batch_auctions.batches.insert(&batch.batch_id, batch);
+		if self.batch_auctions.open_batches.get(&batch.pool_id) == Some(batch.batch_id) {
+			self.batch_auctions.open_batches.remove(&batch.pool_id);
+		}
+	}
+}
+
+// Part of amount_in the sellers of x have to swap against the curve for a uniform price, None if
+// they are not the heavier side. With g = 1 - fee, the average price g*y/(x + g*dx) of the swap
+// must equal amount_out/(amount_in - dx), the price the other side gets for the rest:
+// dx = (g*y*amount_in - amount_out*x) / (g*(y + amount_out))
+pub fn clearing_swap_amount(
+	amount_in: u128,
+	amount_out: u128,
+	x: u128,
+	y: u128,
+	fee_bps: u32,
+) -> Result<Option<u128>, AmmError> {
+	let g = U256::from(BPS_DENOMINATOR - fee_bps as u128);
+	let d = U256::from(BPS_DENOMINATOR);
+	let sell = g * U256::from(y) * U256::from(amount_in);
+	let buy = d * U256::from(amount_out) * U256::from(x);
+	if sell <= buy {
+		return Ok(None)
+	}
+	let dx = (sell - buy) / (g * (U256::from(y) + U256::from(amount_out)));
+	if dx.is_zero() {
+		return Ok(None)
+	}
+	Ok(Some(dx.as_u128().min(amount_in)))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_batch_auction() {
+		let (mut context, mut contract) = setup_pool();
+		contract.set_batch_auction(DEFAULT_POOL_ID, Some(10)).unwrap();
+		for account_id in [accounts(2), accounts(5)] {
+			deposit(&mut contract, &token_a(), &account_id, 1_000);
+			deposit(&mut contract, &token_b(), &account_id, 1_000);
+		}
+
+		testing_env!(context.predecessor_account_id(accounts(2)).block_index(3).build());
+		let batch_id = contract.place_batch_order(DEFAULT_POOL_ID, token_a(), U128(1_000)).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(5)).block_index(9).build());
+		assert_eq!(
+			contract.place_batch_order(DEFAULT_POOL_ID, token_b(), U128(300)).unwrap(),
+			batch_id
+		);
+		assert_eq!(contract.settle_batch(batch_id), Err(AmmError::BatchNotEnded(10)));
+
+		testing_env!(context.block_index(10).build());
+		let price = contract.settle_batch(batch_id).unwrap().0;
+		// Selling pressure on token_a moved the price below the spot price of 0.5
+		assert!(price < PRICE_PRECISION / 2 && price > PRICE_PRECISION * 49 / 100);
+		assert_eq!(contract.settle_batch(batch_id), Err(AmmError::BatchSettled(batch_id)));
+
+		// Both sides got the same price, up to rounding
+		let claimed = contract.claim_batch_order(batch_id).unwrap();
+		assert_eq!(claimed[0].token_id, token_a());
+		assert!(claimed[0].amount.0.abs_diff(mul_div(300, PRICE_PRECISION, price).unwrap()) <= 1);
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		let claimed = contract.claim_batch_order(batch_id).unwrap();
+		assert_eq!(claimed[0].token_id, token_b());
+		assert_eq!(claimed[0].amount.0, mul_div(1_000, price, PRICE_PRECISION).unwrap());
+		assert_eq!(contract.claim_batch_order(batch_id), Err(AmmError::OrderNotFound(batch_id)));
+		assert!(contract
+			.get_batch(batch_id)
+			.unwrap()
+			.unclaimed
+			.iter()
+			.all(|amount| amount.0 <= 1));
+		contract.check_invariants().unwrap();
+	}
+
+	#[test]
+	fn test_batch_refunds() {
+		let (mut context, mut contract) = setup_pool();
+		contract.set_batch_auction(DEFAULT_POOL_ID, Some(10)).unwrap();
+		deposit(&mut contract, &token_a(), &accounts(2), 100);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+
+		// Selling 1 token_a buys nothing, the settlement refunds it
+		testing_env!(context.predecessor_account_id(accounts(2)).block_index(3).build());
+		let batch_id = contract.place_batch_order(DEFAULT_POOL_ID, token_a(), U128(1)).unwrap();
+		testing_env!(context.block_index(10).build());
+		assert_eq!(contract.settle_batch(batch_id), Ok(U128(0)));
+		assert!(contract.get_batch(batch_id).unwrap().refunded);
+		let refund = contract.claim_batch_order(batch_id).unwrap();
+		assert_eq!(refund, vec![TokenAmount { token_id: token_a(), amount: U128(1) }]);
+
+		// Orders of a pool whose batch auctions were disabled can be cancelled
+		let batch_id = contract.place_batch_order(DEFAULT_POOL_ID, token_a(), U128(50)).unwrap();
+		assert_eq!(
+			contract.cancel_batch_order(batch_id),
+			Err(AmmError::BatchNotCancellable(batch_id))
+		);
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		contract.set_batch_auction(DEFAULT_POOL_ID, None).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		contract.cancel_batch_order(batch_id).unwrap();
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(100));
+		assert_eq!(contract.get_batch(batch_id).unwrap().amounts_in, vec![U128(0); 2]);
+		contract.check_invariants().unwrap();
+	}
+}
@@ -0,0 +1,254 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	collections::UnorderedMap,
+	env,
+	json_types::{Base64VecU8, U128, U64},
+	log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId, BlockHeight,
+};
+
+use crate::*;
+
+// Blocks between the commitment and the earliest reveal
+pub const MIN_REVEAL_DELAY_BLOCKS: BlockHeight = 2;
+
+// Blocks after the earliest reveal a commitment can still be revealed, it's stale afterwards
+pub const REVEAL_WINDOW_BLOCKS: BlockHeight = 600;
+
+// Swap parameters hidden behind a commitment until the reveal
+#[derive(BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CommittedSwap {
+	pub pool_id: u64,
+	pub sell_token_id: AccountId,
+	pub buy_token_id: AccountId,
+	pub sell_amount: U128,
+	pub min_amount_out: U128,
+	// Random value that keeps the parameters from being guessed from the hash
+	pub salt: String,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Commitment {
+	pub commitment_id: u64,
+	pub account_id: AccountId,
+	// sha256 of the borsh serialized (account_id, CommittedSwap)
+	pub hash: Base64VecU8,
+	// Escrow the revealed swap sells from. It may exceed the sell amount to hide it.
+	pub token_id: AccountId,
+	pub amount: U128,
+	pub block_height: U64,
+}
+
+// Two-phase swaps against front-running. The swap is committed as a hash with an escrow and
+// revealed at least MIN_REVEAL_DELAY_BLOCKS later, so its parameters aren't public before the
+// block it executes in.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Commitments {
+	// commitment_id:commitment
+	pub commitments: UnorderedMap<u64, Commitment>,
+	pub next_commitment_id: u64,
+}
+
+impl Commitments {
+	pub fn new() -> Self {
+		Self { commitments: UnorderedMap::new(b"commit".to_vec()), next_commitment_id: 0 }
+	}
+}
+
+impl Default for Commitments {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	// Escrows `amount` of token_id from the internal balance of the caller behind the hash
+	#[handle_result]
+	pub fn commit_swap(
+		&mut self,
+		hash: Base64VecU8,
+		token_id: AccountId,
+		amount: U128,
+	) -> Result<u64, AmmError> {
+		if hash.0.len() != 32 {
+			return Err(AmmError::InvalidCommitment)
+		}
+		if amount.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		let account_id = env::predecessor_account_id();
+		self.internal_escrow(&token_id, &account_id, amount.0)?;
+
+		let commitment_id = self.commitments.next_commitment_id;
+		self.commitments.next_commitment_id += 1;
+		self.commitments.commitments.insert(
+			&commitment_id,
+			&Commitment {
+				commitment_id,
+				account_id,
+				hash,
+				token_id,
+				amount,
+				block_height: U64(env::block_height()),
+			},
+		);
+		Ok(commitment_id)
+	}
+
+	// Executes the committed swap with its bounds and returns the rest of the escrow
+	#[handle_result]
+	pub fn reveal_swap(
+		&mut self,
+		commitment_id: u64,
+		swap: CommittedSwap,
+	) -> Result<U128, AmmError> {
+		let commitment = self.get_commitment(commitment_id)?;
+		if commitment.account_id != env::predecessor_account_id() {
+			return Err(AmmError::NotCommitmentOwner(commitment_id))
+		}
+		let opens_at = commitment.block_height.0 + MIN_REVEAL_DELAY_BLOCKS;
+		let block_height = env::block_height();
+		if block_height < opens_at {
+			return Err(AmmError::RevealTooEarly(opens_at))
+		}
+		if block_height > opens_at + REVEAL_WINDOW_BLOCKS {
+			return Err(AmmError::CommitmentExpired(commitment_id))
+		}
+		if hash_committed_swap(&commitment.account_id, &swap) != commitment.hash.0 ||
+			swap.sell_token_id != commitment.token_id ||
+			swap.sell_amount.0 > commitment.amount.0
+		{
+			return Err(AmmError::InvalidCommitment)
+		}
+
+		self.commitments.commitments.remove(&commitment_id);
+		self.internal_release(&commitment.token_id, &commitment.account_id, commitment.amount.0)?;
+		let amount_out = self.internal_swap_tokens(
+			&commitment.account_id,
+			swap.pool_id,
+			swap.buy_token_id,
+			swap.sell_token_id,
+			swap.sell_amount,
+			None,
+		)?;
+		if amount_out.0 < swap.min_amount_out.0 {
+			return Err(AmmError::SlippageExceeded)
+		}
+		log!("Commitment {} revealed", commitment_id);
+		Ok(amount_out)
+	}
+
+	// Refunds the escrow of a stale commitment to its owner. Callable by anyone.
+	#[handle_result]
+	pub fn cancel_commitment(&mut self, commitment_id: u64) -> Result<U128, AmmError> {
+		let commitment = self.get_commitment(commitment_id)?;
+		let stale_at = commitment.block_height.0 + MIN_REVEAL_DELAY_BLOCKS + REVEAL_WINDOW_BLOCKS;
+		if env::block_height() <= stale_at {
+			return Err(AmmError::CommitmentNotExpired(commitment_id))
+		}
+		self.commitments.commitments.remove(&commitment_id);
+		self.internal_release(&commitment.token_id, &commitment.account_id, commitment.amount.0)?;
+		log!("Commitment {} cancelled", commitment_id);
+		Ok(commitment.amount)
+	}
+
+	#[handle_result]
+	pub fn get_commitment(&self, commitment_id: u64) -> Result<Commitment, AmmError> {
+		self.commitments
+			.commitments
+			.get(&commitment_id)
+			.ok_or(AmmError::CommitmentNotFound(commitment_id))
+	}
+
+	pub fn get_commitments(&self, from: Option<u64>, limit: Option<u64>) -> Vec<Commitment> {
+		self.commitments
+			.commitments
+			.values()
+			.skip(from.unwrap_or(0) as usize)
+			.take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+			.collect()
+	}
+}
+
+// Hash to commit to, clients compute it locally so the parameters stay private
+pub fn hash_committed_swap(account_id: &AccountId, swap: &CommittedSwap) -> Vec<u8> {
+	env::sha256(&(account_id, swap).try_to_vec().unwrap())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	fn committed_swap(sell_amount: u128) -> CommittedSwap {
+		CommittedSwap {
+			pool_id: DEFAULT_POOL_ID,
+			sell_token_id: token_a(),
+			buy_token_id: token_b(),
+			sell_amount: U128(sell_amount),
+			min_amount_out: U128(calc_dy(POOL_A, POOL_B, sell_amount).unwrap()),
+			salt: "8c1f".to_string(),
+		}
+	}
+
+	#[test]
+	fn test_commit_and_reveal() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 5_000);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+		testing_env!(context.predecessor_account_id(accounts(2)).block_index(10).build());
+
+		// The escrow hides the sell amount
+		let hash = hash_committed_swap(&accounts(2), &committed_swap(1_000));
+		let commitment_id =
+			contract.commit_swap(Base64VecU8(hash), token_a(), U128(3_000)).unwrap();
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(2_000));
+		assert_eq!(
+			contract.reveal_swap(commitment_id, committed_swap(1_000)),
+			Err(AmmError::RevealTooEarly(12))
+		);
+
+		testing_env!(context.predecessor_account_id(accounts(5)).block_index(12).build());
+		assert_eq!(
+			contract.reveal_swap(commitment_id, committed_swap(1_000)),
+			Err(AmmError::NotCommitmentOwner(commitment_id))
+		);
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		assert_eq!(
+			contract.reveal_swap(commitment_id, committed_swap(2_000)),
+			Err(AmmError::InvalidCommitment)
+		);
+		let amount_out = contract.reveal_swap(commitment_id, committed_swap(1_000)).unwrap();
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(4_000));
+		assert_eq!(contract.ft_balance_of(token_b(), accounts(2)).unwrap(), amount_out);
+		assert_eq!(
+			contract.get_commitment(commitment_id),
+			Err(AmmError::CommitmentNotFound(commitment_id))
+		);
+	}
+
+	#[test]
+	fn test_cancel_stale_commitment() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 1_000);
+		testing_env!(context.predecessor_account_id(accounts(2)).block_index(10).build());
+		let commitment_id =
+			contract.commit_swap(Base64VecU8(vec![0; 32]), token_a(), U128(1_000)).unwrap();
+
+		testing_env!(context.predecessor_account_id(accounts(5)).block_index(612).build());
+		assert_eq!(
+			contract.cancel_commitment(commitment_id),
+			Err(AmmError::CommitmentNotExpired(commitment_id))
+		);
+		testing_env!(context.block_index(613).build());
+		assert_eq!(contract.cancel_commitment(commitment_id).unwrap(), U128(1_000));
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(1_000));
+	}
+}
@@ -0,0 +1,324 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	collections::UnorderedMap,
+	env,
+	json_types::{U128, U64},
+	log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId, Timestamp,
+};
+
+use crate::*;
+
+// TWAP window the output of every DCA swap is compared to
+pub const DCA_TWAP_WINDOW_SEC: u64 = 600;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DcaOrder {
+	pub order_id: u64,
+	pub account_id: AccountId,
+	pub pool_id: u64,
+	pub sell_token_id: AccountId,
+	pub buy_token_id: AccountId,
+	// Sold by every execution
+	pub amount_per_swap: U128,
+	pub swaps_left: u32,
+	pub interval_sec: u32,
+	// Paid in sell_token to the keeper on every execution
+	pub keeper_tip: U128,
+	// Highest accepted shortfall of the output against the TWAP price
+	pub max_slippage_bps: u32,
+	pub next_execution: U64,
+}
+
+impl DcaOrder {
+	// Sell tokens still escrowed, swap amounts and keeper tips
+	pub fn escrow(&self) -> Result<u128, AmmError> {
+		self.amount_per_swap
+			.0
+			.checked_add(self.keeper_tip.0)
+			.and_then(|amount| amount.checked_mul(self.swaps_left as u128))
+			.ok_or(AmmError::MathOverflow)
+	}
+}
+
+// Dollar cost averaging orders. The whole amount is escrowed on creation and sold in equal
+// parts by keepers, who call `execute_dca` once the interval has elapsed and earn a tip.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct DcaOrders {
+	// order_id:order
+	pub orders: UnorderedMap<u64, DcaOrder>,
+	pub next_order_id: u64,
+}
+
+impl DcaOrders {
+	pub fn new() -> Self {
+		Self { orders: UnorderedMap::new(b"dca".to_vec()), next_order_id: 0 }
+	}
+}
+
+impl Default for DcaOrders {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NewDcaOrder {
+	pub pool_id: u64,
+	pub sell_token_id: AccountId,
+	pub buy_token_id: AccountId,
+	pub amount_per_swap: U128,
+	pub swaps: u32,
+	pub interval_sec: u32,
+	pub keeper_tip: U128,
+	pub max_slippage_bps: u32,
+}
+
+#[near_bindgen]
+impl Contract {
+	// Escrows (amount_per_swap + keeper_tip) * swaps of sell_token_id from the internal balance of
+	// the caller. The first swap can be executed right away.
+	#[handle_result]
+	pub fn create_dca_order(&mut self, order: NewDcaOrder) -> Result<u64, AmmError> {
+		if order.sell_token_id == order.buy_token_id {
+			return Err(AmmError::TokensEqual)
+		}
+		let pool = self.get_pool(order.pool_id)?;
+		pool.token_index(&order.sell_token_id)?;
+		pool.token_index(&order.buy_token_id)?;
+		if order.amount_per_swap.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		if order.swaps == 0 ||
+			order.interval_sec == 0 ||
+			order.max_slippage_bps as u128 > BPS_DENOMINATOR
+		{
+			return Err(AmmError::InvalidDcaOrder)
+		}
+		// Every swap credits the bought amount to the owner
+		let account_id = env::predecessor_account_id();
+		internal_balance_of(&self.get_token(&order.buy_token_id)?, &account_id)?;
+
+		let order_id = self.dca_orders.next_order_id;
+		let order = DcaOrder {
+			order_id,
+			account_id,
+			pool_id: order.pool_id,
+			sell_token_id: order.sell_token_id,
+			buy_token_id: order.buy_token_id,
+			amount_per_swap: order.amount_per_swap,
+			swaps_left: order.swaps,
+			interval_sec: order.interval_sec,
+			keeper_tip: order.keeper_tip,
+			max_slippage_bps: order.max_slippage_bps,
+			next_execution: U64(env::block_timestamp()),
+		};
+		self.internal_escrow(&order.sell_token_id, &order.account_id, order.escrow()?)?;
+
+		self.dca_orders.next_order_id += 1;
+		self.dca_orders.orders.insert(&order_id, &order);
+		log!("DCA order {} created by {}", order_id, order.account_id);
+		Ok(order_id)
+	}
+
+	// Executes the next swap of a due order. Callable by anyone, the tip is credited to the
+	// internal sell_token balance of the caller.
+	#[handle_result]
+	pub fn execute_dca(&mut self, order_id: u64) -> Result<U128, AmmError> {
+		let mut order = self.get_dca_order(order_id).ok_or(AmmError::OrderNotFound(order_id))?;
+		self.assert_not_paused(Some(order.pool_id), PauseAction::Swaps)?;
+		let now = env::block_timestamp();
+		if now < order.next_execution.0 {
+			return Err(AmmError::OrderNotDue(order_id))
+		}
+		let keeper_id = env::predecessor_account_id();
+		internal_balance_of(&self.get_token(&order.sell_token_id)?, &keeper_id)?;
+
+		let min_amount_out = mul_div(
+			self.twap_amount_out(
+				order.pool_id,
+				&order.sell_token_id,
+				order.amount_per_swap.0,
+				now,
+			)?,
+			BPS_DENOMINATOR - order.max_slippage_bps as u128,
+			BPS_DENOMINATOR,
+		)?;
+		let (amount_out, _) = self.quote_swap(
+			order.pool_id,
+			&order.sell_token_id,
+			&order.buy_token_id,
+			order.amount_per_swap.0,
+		)?;
+		if amount_out < min_amount_out {
+			return Err(AmmError::SlippageExceeded)
+		}
+		let amount_out = match self.internal_swap(
+			order.pool_id,
+			&order.sell_token_id,
+			&order.buy_token_id,
+			order.amount_per_swap.0,
+		)? {
+			Some((amount_out, _)) => amount_out,
+			None => return Ok(U128(0)),
+		};
+		self.sub_escrow(&order.sell_token_id, order.amount_per_swap.0);
+		self.internal_deposit(&order.buy_token_id, &order.account_id, amount_out)?;
+		self.internal_release(&order.sell_token_id, &keeper_id, order.keeper_tip.0)?;
+		self.match_limit_orders(order.pool_id, &order.buy_token_id)?;
+
+		order.swaps_left -= 1;
+		order.next_execution = U64(now + order.interval_sec as u64 * NANOS_PER_SECOND);
+		if order.swaps_left == 0 {
+			self.dca_orders.orders.remove(&order_id);
+			log!("DCA order {} completed", order_id);
+		} else {
+			self.dca_orders.orders.insert(&order_id, &order);
+		}
+		self.assert_invariants()?;
+		Ok(U128(amount_out))
+	}
+
+	// Closes the order and returns the escrow left to the internal balance
+	#[handle_result]
+	pub fn cancel_dca_order(&mut self, order_id: u64) -> Result<U128, AmmError> {
+		let order = self.get_dca_order(order_id).ok_or(AmmError::OrderNotFound(order_id))?;
+		if order.account_id != env::predecessor_account_id() {
+			return Err(AmmError::NotOrderOwner(order_id))
+		}
+		let escrow = order.escrow()?;
+		self.internal_release(&order.sell_token_id, &order.account_id, escrow)?;
+		self.dca_orders.orders.remove(&order_id);
+		log!("DCA order {} cancelled", order_id);
+		Ok(U128(escrow))
+	}
+
+	pub fn get_dca_order(&self, order_id: u64) -> Option<DcaOrder> {
+		self.dca_orders.orders.get(&order_id)
+	}
+
+	// Pending orders, due only if `due` is set, so keepers can find the work
+	pub fn get_dca_orders(
+		&self,
+		from: Option<u64>,
+		limit: Option<u64>,
+		due: Option<bool>,
+	) -> Vec<DcaOrder> {
+		let now = env::block_timestamp();
+		let due = due.unwrap_or(false);
+		self.dca_orders
+			.orders
+			.values()
+			.filter(|order| !due || order.next_execution.0 <= now)
+			.skip(from.unwrap_or(0) as usize)
+			.take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+			.collect()
+	}
+}
+
+impl Contract {
+	// Amount of token_out amount_in is worth at the TWAP price of the pool
+	fn twap_amount_out(
+		&self,
+		pool_id: u64,
+		token_in: &AccountId,
+		amount_in: u128,
+		now: Timestamp,
+	) -> Result<u128, AmmError> {
+		let pool = self.get_pool(pool_id)?;
+		let twap = pool
+			.price_oracle
+			.twap(now, DCA_TWAP_WINDOW_SEC * NANOS_PER_SECOND)
+			.filter(|twap| *twap > 0)
+			.ok_or(AmmError::NoPriceHistory(pool_id))?;
+		// The oracle prices the first token of the pool in the second one
+		if pool.token_index(token_in)? == 0 {
+			mul_div(amount_in, twap, PRICE_PRECISION)
+		} else {
+			mul_div(amount_in, PRICE_PRECISION, twap)
+		}
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	fn new_order(swaps: u32, max_slippage_bps: u32) -> NewDcaOrder {
+		NewDcaOrder {
+			pool_id: DEFAULT_POOL_ID,
+			sell_token_id: token_a(),
+			buy_token_id: token_b(),
+			amount_per_swap: U128(1_000),
+			swaps,
+			interval_sec: 60,
+			keeper_tip: U128(10),
+			max_slippage_bps,
+		}
+	}
+
+	#[test]
+	fn test_execute_dca() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 3_000);
+		deposit(&mut contract, &token_a(), &accounts(5), 0);
+
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		assert_eq!(contract.create_dca_order(new_order(0, 100)), Err(AmmError::InvalidDcaOrder));
+		assert_eq!(
+			contract.create_dca_order(new_order(2, 100)),
+			Err(AmmError::AccountNotRegistered(accounts(2)))
+		);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+		assert_eq!(contract.create_dca_order(new_order(3, 100)), Err(AmmError::NotEnoughBalance));
+		let order_id = contract.create_dca_order(new_order(2, 100)).unwrap();
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(980));
+
+		// A keeper executes the first swap once the pool has some price history
+		let minute = 60 * NANOS_PER_SECOND;
+		testing_env!(context.predecessor_account_id(accounts(5)).block_timestamp(minute).build());
+		assert_eq!(contract.get_dca_orders(None, None, Some(true)).len(), 1);
+		let first = contract.execute_dca(order_id).unwrap();
+		assert_eq!(first.0, calc_dy(POOL_A, POOL_B, 1_000).unwrap());
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(5)).unwrap(), U128(10));
+		assert_eq!(contract.execute_dca(order_id), Err(AmmError::OrderNotDue(order_id)));
+		assert!(contract.get_dca_orders(None, None, Some(true)).is_empty());
+
+		testing_env!(context.block_timestamp(2 * minute).build());
+		let second = contract.execute_dca(order_id).unwrap();
+		assert_eq!(
+			contract.ft_balance_of(token_b(), accounts(2)).unwrap(),
+			U128(first.0 + second.0)
+		);
+		assert_eq!(contract.get_dca_order(order_id), None);
+	}
+
+	#[test]
+	fn test_dca_slippage_and_cancel() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 100_020);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		let mut order = new_order(2, 10);
+		order.amount_per_swap = U128(u128::MAX);
+		assert_eq!(contract.create_dca_order(order), Err(AmmError::MathOverflow));
+		let mut order = new_order(2, 10);
+		order.amount_per_swap = U128(50_000);
+		let order_id = contract.create_dca_order(order).unwrap();
+
+		// Selling 1/8 of the reserve moves the price far more than 0.1%
+		testing_env!(context.block_timestamp(60 * NANOS_PER_SECOND).build());
+		assert_eq!(contract.execute_dca(order_id), Err(AmmError::SlippageExceeded));
+
+		assert_eq!(contract.cancel_dca_order(order_id).unwrap(), U128(100_020));
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(100_020));
+		assert_eq!(contract.cancel_dca_order(order_id), Err(AmmError::OrderNotFound(order_id)));
+	}
+}
@@ -0,0 +1,257 @@
+use std::fmt;
+
+use near_sdk::{env, AccountId, FunctionError};
+
+use crate::*;
+
+// Every error the contract can fail with. The code of a variant never changes, so clients can
+// map them without parsing messages, see docs/errors.md. New variants get the next free code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmmError {
+	TokensEqual,
+	TokenNotSupported(AccountId),
+	TokenMetadataNotFound(AccountId),
+	IncorrectProportions,
+	NotOwner,
+	NotProposedOwner,
+	NoPendingOwner,
+	MissingRole(Role),
+	Paused(PauseAction),
+	PoolNotFound(u64),
+	InvalidCircuitBreaker,
+	NoContractCode,
+	UnknownStateVersion(u32),
+	MathOverflow,
+	DivisionByZero,
+	EmptyPool,
+	NoLiquidityShares,
+	NotEnoughBalance,
+	AccountNotRegistered(AccountId),
+	WrapNearNotConfigured,
+	ZeroAmount,
+	InvalidFee(u32),
+	OrderNotFound(u64),
+	NotOrderOwner(u64),
+	InvalidPrice,
+	OrderBookFull,
+	OrderNotFilled(u64),
+	InvalidDcaOrder,
+	OrderNotDue(u64),
+	NoPriceHistory(u64),
+	SlippageExceeded,
+	FarmNotFound(u64),
+	InvalidFarm,
+	InvalidDynamicFee,
+	TokenAlreadyRegistered(AccountId),
+	TokenInUse(AccountId),
+	InvalidMessage,
+	NoSurplus(AccountId),
+	InvariantViolated(String),
+	DeadlineExceeded(u64),
+	PriceDeviationExceeded(u32),
+	MissingActionAmount(u32),
+	InvalidIntent,
+	InvalidIntentKey,
+	InvalidSignature,
+	NonceUsed(u64),
+	CommitmentNotFound(u64),
+	InvalidCommitment,
+	RevealTooEarly(u64),
+	CommitmentExpired(u64),
+	CommitmentNotExpired(u64),
+	BatchAuctionDisabled(u64),
+	BatchNotFound(u64),
+	BatchNotEnded(u64),
+	BatchSettled(u64),
+	BatchNotSettled(u64),
+	InvalidMultiPool,
+	MultiPoolNotFound(u64),
+	BatchNotCancellable(u64),
+	OrderTooSmall(u128),
+	NotCommitmentOwner(u64),
+	LedgerNotIndexed(AccountId),
+}
+
+impl AmmError {
+	pub fn code(&self) -> &'static str {
+		match self {
+			AmmError::TokensEqual => "E001",
+			AmmError::TokenNotSupported(_) => "E002",
+			AmmError::TokenMetadataNotFound(_) => "E003",
+			AmmError::IncorrectProportions => "E004",
+			AmmError::NotOwner => "E005",
+			AmmError::NotProposedOwner => "E006",
+			AmmError::NoPendingOwner => "E007",
+			AmmError::MissingRole(_) => "E008",
+			AmmError::Paused(_) => "E009",
+			AmmError::PoolNotFound(_) => "E010",
+			AmmError::InvalidCircuitBreaker => "E011",
+			AmmError::NoContractCode => "E012",
+			AmmError::UnknownStateVersion(_) => "E013",
+			AmmError::MathOverflow => "E014",
+			AmmError::DivisionByZero => "E015",
+			AmmError::EmptyPool => "E016",
+			AmmError::NoLiquidityShares => "E017",
+			AmmError::NotEnoughBalance => "E018",
+			AmmError::AccountNotRegistered(_) => "E019",
+			AmmError::WrapNearNotConfigured => "E020",
+			AmmError::ZeroAmount => "E021",
+			AmmError::InvalidFee(_) => "E022",
+			AmmError::OrderNotFound(_) => "E023",
+			AmmError::NotOrderOwner(_) => "E024",
+			AmmError::InvalidPrice => "E025",
+			AmmError::OrderBookFull => "E026",
+			AmmError::OrderNotFilled(_) => "E027",
+			AmmError::InvalidDcaOrder => "E028",
+			AmmError::OrderNotDue(_) => "E029",
+			AmmError::NoPriceHistory(_) => "E030",
+			AmmError::SlippageExceeded => "E031",
+			AmmError::FarmNotFound(_) => "E032",
+			AmmError::InvalidFarm => "E033",
+			AmmError::InvalidDynamicFee => "E034",
+			AmmError::TokenAlreadyRegistered(_) => "E035",
+			AmmError::TokenInUse(_) => "E036",
+			AmmError::InvalidMessage => "E037",
+			AmmError::NoSurplus(_) => "E038",
+			AmmError::InvariantViolated(_) => "E039",
+			AmmError::DeadlineExceeded(_) => "E040",
+			AmmError::PriceDeviationExceeded(_) => "E041",
+			AmmError::MissingActionAmount(_) => "E042",
+			AmmError::InvalidIntent => "E043",
+			AmmError::InvalidIntentKey => "E044",
+			AmmError::InvalidSignature => "E045",
+			AmmError::NonceUsed(_) => "E046",
+			AmmError::CommitmentNotFound(_) => "E047",
+			AmmError::InvalidCommitment => "E048",
+			AmmError::RevealTooEarly(_) => "E049",
+			AmmError::CommitmentExpired(_) => "E050",
+			AmmError::CommitmentNotExpired(_) => "E051",
+			AmmError::BatchAuctionDisabled(_) => "E052",
+			AmmError::BatchNotFound(_) => "E053",
+			AmmError::BatchNotEnded(_) => "E054",
+			AmmError::BatchSettled(_) => "E055",
+			AmmError::BatchNotSettled(_) => "E056",
+			AmmError::InvalidMultiPool => "E057",
+			AmmError::MultiPoolNotFound(_) => "E058",
+			AmmError::BatchNotCancellable(_) => "E059",
+			AmmError::OrderTooSmall(_) => "E060",
+			AmmError::NotCommitmentOwner(_) => "E061",
+			AmmError::LedgerNotIndexed(_) => "E062",
+		}
+	}
+}
+
+impl fmt::Display for AmmError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: ", self.code())?;
+		match self {
+			AmmError::TokensEqual => write!(f, "tokens can't be equal"),
+			AmmError::TokenNotSupported(token_id) => write!(f, "token {} not supported", token_id),
+			AmmError::TokenMetadataNotFound(token_id) =>
+				write!(f, "token {} metadata not found", token_id),
+			AmmError::IncorrectProportions =>
+				write!(f, "incorrect proportions for replenishing the liquidity pool"),
+			AmmError::NotOwner => write!(f, "only the owner can call this method"),
+			AmmError::NotProposedOwner => write!(f, "only the proposed owner can accept ownership"),
+			AmmError::NoPendingOwner => write!(f, "no pending owner"),
+			AmmError::MissingRole(role) => write!(f, "missing role {:?}", role),
+			AmmError::Paused(action) => write!(f, "{:?} are paused", action),
+			AmmError::PoolNotFound(pool_id) => write!(f, "pool {} not found", pool_id),
+			AmmError::InvalidCircuitBreaker => write!(f, "invalid circuit breaker config"),
+			AmmError::NoContractCode => write!(f, "no contract code"),
+			AmmError::UnknownStateVersion(version) =>
+				write!(f, "unknown state version {}", version),
+			AmmError::MathOverflow => write!(f, "math overflow"),
+			AmmError::DivisionByZero => write!(f, "division by zero"),
+			AmmError::EmptyPool => write!(f, "pool has no liquidity"),
+			AmmError::NoLiquidityShares => write!(f, "account has no liquidity shares"),
+			AmmError::NotEnoughBalance => write!(f, "not enough balance"),
+			AmmError::AccountNotRegistered(account_id) =>
+				write!(f, "account {} not registered", account_id),
+			AmmError::WrapNearNotConfigured => write!(f, "wNEAR token is not configured"),
+			AmmError::ZeroAmount => write!(f, "amount must be positive"),
+			AmmError::InvalidFee(fee_bps) => write!(f, "invalid fee {} bps", fee_bps),
+			AmmError::OrderNotFound(order_id) => write!(f, "order {} not found", order_id),
+			AmmError::NotOrderOwner(order_id) =>
+				write!(f, "only the owner of order {} can call this method", order_id),
+			AmmError::InvalidPrice => write!(f, "price must be positive"),
+			AmmError::OrderBookFull => write!(f, "order book is full"),
+			AmmError::OrderNotFilled(order_id) => write!(f, "order {} is not filled", order_id),
+			AmmError::InvalidDcaOrder => write!(f, "invalid DCA order"),
+			AmmError::OrderNotDue(order_id) => write!(f, "order {} is not due yet", order_id),
+			AmmError::NoPriceHistory(pool_id) => write!(f, "pool {} has no price history", pool_id),
+			AmmError::SlippageExceeded => write!(f, "slippage exceeded"),
+			AmmError::FarmNotFound(farm_id) => write!(f, "farm {} not found", farm_id),
+			AmmError::InvalidFarm => write!(f, "invalid farm period"),
+			AmmError::InvalidDynamicFee => write!(f, "invalid dynamic fee config"),
+			AmmError::TokenAlreadyRegistered(token_id) =>
+				write!(f, "token {} is already registered", token_id),
+			AmmError::TokenInUse(token_id) =>
+				write!(f, "token {} has balances or is used by a pool", token_id),
+			AmmError::InvalidMessage => write!(f, "invalid ft_transfer_call msg"),
+			AmmError::NoSurplus(token_id) => write!(f, "no synced surplus of {}", token_id),
+			AmmError::InvariantViolated(reason) => write!(f, "invariant violated: {}", reason),
+			AmmError::DeadlineExceeded(deadline) =>
+				write!(f, "transaction deadline {} exceeded", deadline),
+			AmmError::PriceDeviationExceeded(deviation_bps) =>
+				write!(f, "pool price moved {} bps from the expected reserves", deviation_bps),
+			AmmError::MissingActionAmount(index) =>
+				write!(f, "action {} has no amount and follows no swap", index),
+			AmmError::InvalidIntent => write!(f, "invalid swap intent"),
+			AmmError::InvalidIntentKey => write!(f, "invalid intent key"),
+			AmmError::InvalidSignature => write!(f, "invalid intent signature"),
+			AmmError::NonceUsed(nonce) => write!(f, "nonce {} is already used", nonce),
+			AmmError::CommitmentNotFound(commitment_id) =>
+				write!(f, "commitment {} not found", commitment_id),
+			AmmError::InvalidCommitment => write!(f, "revealed swap doesn't match the commitment"),
+			AmmError::RevealTooEarly(block_height) =>
+				write!(f, "commitment can be revealed from block {}", block_height),
+			AmmError::CommitmentExpired(commitment_id) =>
+				write!(f, "commitment {} expired", commitment_id),
+			AmmError::CommitmentNotExpired(commitment_id) =>
+				write!(f, "commitment {} is not stale yet", commitment_id),
+			AmmError::BatchAuctionDisabled(pool_id) =>
+				write!(f, "pool {} has no batch auctions", pool_id),
+			AmmError::BatchNotFound(batch_id) => write!(f, "batch {} not found", batch_id),
+			AmmError::BatchNotEnded(end_block) =>
+				write!(f, "batch can be settled from block {}", end_block),
+			AmmError::BatchSettled(batch_id) => write!(f, "batch {} is already settled", batch_id),
+			AmmError::BatchNotSettled(batch_id) => write!(f, "batch {} is not settled", batch_id),
+			AmmError::InvalidMultiPool => write!(f, "invalid multi-asset pool"),
+			AmmError::MultiPoolNotFound(pool_id) =>
+				write!(f, "multi-asset pool {} not found", pool_id),
+			AmmError::BatchNotCancellable(batch_id) =>
+				write!(f, "batch {} can still be settled", batch_id),
+			AmmError::OrderTooSmall(min_amount) =>
+				write!(f, "order is below the minimum amount of {}", min_amount),
+			AmmError::NotCommitmentOwner(commitment_id) =>
+				write!(f, "only the owner of commitment {} can reveal it", commitment_id),
+			AmmError::LedgerNotIndexed(token_id) =>
+				write!(f, "holders of {} were registered before they were indexed", token_id),
+		}
+	}
+}
+
+impl FunctionError for AmmError {
+	fn panic(&self) -> ! {
+		env::panic_str(&self.to_string())
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::test_utils::accounts;
+
+	use super::*;
+
+	#[test]
+	fn test_error_messages() {
+		assert_eq!(AmmError::TokensEqual.to_string(), "E001: tokens can't be equal");
+		assert_eq!(
+			AmmError::TokenNotSupported(accounts(1)).to_string(),
+			"E002: token bob not supported"
+		);
+		assert_eq!(AmmError::Paused(PauseAction::Swaps).to_string(), "E009: Swaps are paused");
+	}
+}
@@ -0,0 +1,35 @@
+use near_sdk::{json_types::U128, log, serde::Serialize, serde_json::json, AccountId};
+
+// NEP-297 events, indexers pick them up from the logs
+pub const EVENT_STANDARD: &str = "amm";
+pub const EVENT_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapEvent<'a> {
+	pub account_id: &'a AccountId,
+	pub pool_id: u64,
+	pub token_in: &'a AccountId,
+	pub amount_in: U128,
+	pub token_out: &'a AccountId,
+	pub amount_out: U128,
+	// Swap fee in token_in, including the referral fee
+	pub fee: U128,
+	// Effective fee rate, may change with volatility
+	pub fee_bps: u32,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub referral_id: Option<&'a AccountId>,
+	pub referral_fee: U128,
+}
+
+pub(crate) fn emit_event<T: Serialize>(event: &str, data: &T) {
+	log!(
+		"EVENT_JSON:{}",
+		json!({
+			"standard": EVENT_STANDARD,
+			"version": EVENT_VERSION,
+			"event": event,
+			"data": [data],
+		})
+	);
+}
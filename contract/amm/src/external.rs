@@ -13,12 +13,39 @@ pub trait SelfContract {
 		contract_id: AccountId,
 		#[callback] metadata: FungibleTokenMetadata,
 	);
-	fn withdraw_tokens_callback(&mut self, token_name: String, amount: U128);
+	fn withdraw_tokens_callback(
+		&mut self,
+		account_id: AccountId,
+		token_name: AccountId,
+		amount: U128,
+	);
+	fn on_near_deposit(&mut self, wrap_near_id: AccountId, account_id: AccountId, amount: U128);
+	fn on_near_swap(
+		&mut self,
+		wrap_near_id: AccountId,
+		account_id: AccountId,
+		amount: U128,
+		buy_token_id: AccountId,
+		min_amount_out: U128,
+		referral_id: Option<AccountId>,
+	);
+	fn on_near_withdraw(&mut self, wrap_near_id: AccountId, account_id: AccountId, amount: U128);
+	fn on_farm_rewards_claimed(&mut self, farm_id: u64, account_id: AccountId, amount: U128);
+	fn on_unallocated_rewards_reclaimed(&mut self, farm_id: u64, amount: U128);
+	fn on_sync(&mut self, token_id: AccountId);
 }
 
 // FT Contract interface
 #[ext_contract(ext_ft)]
 pub trait FtContract {
 	fn ft_metadata(&self) -> FungibleTokenMetadata;
+	fn ft_balance_of(&self, account_id: AccountId) -> U128;
 	fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
 }
+
+// wNEAR contract interface, e.g. wrap.near
+#[ext_contract(ext_wrap_near)]
+pub trait WrapNearContract {
+	fn near_deposit(&mut self);
+	fn near_withdraw(&mut self, amount: U128);
+}
@@ -0,0 +1,457 @@
+use std::cmp::min;
+
+use near_sdk::{
+	assert_one_yocto,
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	collections::{LookupMap, Vector},
+	env,
+	json_types::{U128, U64},
+	log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId, Gas, Promise, PromiseResult, Timestamp,
+};
+
+use crate::*;
+
+// Scale of the reward per staked share accumulator
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000_000_000_000_000;
+
+// Rewards of a pool's LP stakers, streamed linearly from `start_at` to `end_at`
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Farm {
+	pub farm_id: u64,
+	pub pool_id: u64,
+	pub reward_token_id: AccountId,
+	pub start_at: U64,
+	pub end_at: U64,
+	// Rewards streamed from start_at to end_at
+	pub total_reward: U128,
+	// Rewards streamed so far
+	pub released: U128,
+	// Rewards streamed while nothing was staked, the owner can reclaim them
+	pub unallocated: U128,
+	pub total_staked: U128,
+	// Rewards per staked share, scaled by REWARD_PRECISION
+	pub reward_per_share: U128,
+}
+
+impl Farm {
+	// Moves rewards streamed since the last update to the accumulator
+	fn update(&mut self, now: Timestamp) -> Result<(), AmmError> {
+		let (start_at, end_at) = (self.start_at.0, self.end_at.0);
+		let released = if now <= start_at {
+			0
+		} else {
+			mul_div(
+				self.total_reward.0,
+				(min(now, end_at) - start_at) as u128,
+				(end_at - start_at) as u128,
+			)?
+		};
+		let streamed = released - self.released.0;
+		if self.total_staked.0 > 0 {
+			self.reward_per_share.0 += mul_div(streamed, REWARD_PRECISION, self.total_staked.0)?;
+		} else {
+			self.unallocated.0 += streamed;
+		}
+		self.released = U128(released);
+		Ok(())
+	}
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FarmStake {
+	pub shares: U128,
+	// Farm reward_per_share the unclaimed rewards were settled at
+	pub reward_per_share_paid: U128,
+	pub unclaimed: U128,
+}
+
+impl Default for FarmStake {
+	fn default() -> Self {
+		Self { shares: U128(0), reward_per_share_paid: U128(0), unclaimed: U128(0) }
+	}
+}
+
+impl FarmStake {
+	fn settle(&mut self, farm: &Farm) -> Result<(), AmmError> {
+		let earned = mul_div(
+			self.shares.0,
+			farm.reward_per_share.0 - self.reward_per_share_paid.0,
+			REWARD_PRECISION,
+		)?;
+		self.unclaimed.0 += earned;
+		self.reward_per_share_paid = farm.reward_per_share;
+		Ok(())
+	}
+}
+
+// Liquidity mining. LP shares are staked by moving them to the AMM account in token_lp, so the
+// total supply and the pool share math don't change.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Farming {
+	pub farms: Vector<Farm>,
+	// (farm_id, account_id):stake
+	pub stakes: LookupMap<(u64, AccountId), FarmStake>,
+}
+
+impl Farming {
+	pub fn new() -> Self {
+		Self { farms: Vector::new(b"farms".to_vec()), stakes: LookupMap::new(b"stakes".to_vec()) }
+	}
+}
+
+impl Default for Farming {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	// Rewards are funded with `ft_transfer_call` of reward_token_id to the AMM with the msg
+	// `{"farm": {"farm_id": <farm_id>}}` until the farm ends
+	#[handle_result]
+	pub fn create_farm(
+		&mut self,
+		pool_id: u64,
+		reward_token_id: AccountId,
+		start_at: U64,
+		end_at: U64,
+	) -> Result<u64, AmmError> {
+		self.assert_owner()?;
+		self.assert_pool_exists(pool_id)?;
+		if start_at.0 >= end_at.0 || end_at.0 <= env::block_timestamp() {
+			return Err(AmmError::InvalidFarm)
+		}
+		let farm_id = self.farming.farms.len();
+		self.farming.farms.push(&Farm {
+			farm_id,
+			pool_id,
+			reward_token_id,
+			start_at,
+			end_at,
+			total_reward: U128(0),
+			released: U128(0),
+			unallocated: U128(0),
+			total_staked: U128(0),
+			reward_per_share: U128(0),
+		});
+		log!("Farm {} created for pool {}", farm_id, pool_id);
+		Ok(farm_id)
+	}
+
+	#[handle_result]
+	pub fn stake_lp(&mut self, farm_id: u64, shares: U128) -> Result<(), AmmError> {
+		if shares.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		let account_id = env::predecessor_account_id();
+		if internal_balance_of(&self.token_lp, &account_id)? < shares.0 {
+			return Err(AmmError::NotEnoughBalance)
+		}
+		let (mut farm, mut stake) = self.settle_farm_stake(farm_id, &account_id)?;
+		let pool_owner_id = env::current_account_id();
+		if !self.token_lp.accounts.contains_key(&pool_owner_id) {
+			self.token_lp.internal_register_account(&pool_owner_id);
+			self.add_token_holder(&pool_owner_id, &pool_owner_id);
+		}
+		self.token_lp.internal_transfer(&account_id, &pool_owner_id, shares.0, None);
+		stake.shares.0 += shares.0;
+		farm.total_staked.0 += shares.0;
+		self.save_farm_stake(&farm, &account_id, &stake);
+		log!("{} staked {} shares in farm {}", account_id, shares.0, farm_id);
+		Ok(())
+	}
+
+	// Returns the staked LP shares, the earned rewards stay claimable
+	#[handle_result]
+	pub fn unstake_lp(&mut self, farm_id: u64, shares: U128) -> Result<(), AmmError> {
+		let account_id = env::predecessor_account_id();
+		let (mut farm, mut stake) = self.settle_farm_stake(farm_id, &account_id)?;
+		if shares.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		if stake.shares.0 < shares.0 {
+			return Err(AmmError::NotEnoughBalance)
+		}
+		self.token_lp
+			.internal_transfer(&env::current_account_id(), &account_id, shares.0, None);
+		stake.shares.0 -= shares.0;
+		farm.total_staked.0 -= shares.0;
+		self.save_farm_stake(&farm, &account_id, &stake);
+		log!("{} unstaked {} shares from farm {}", account_id, shares.0, farm_id);
+		Ok(())
+	}
+
+	// Sends the earned rewards to the caller's wallet
+	#[payable]
+	#[handle_result]
+	pub fn claim_farm_rewards(&mut self, farm_id: u64) -> Result<Promise, AmmError> {
+		assert_one_yocto();
+		let account_id = env::predecessor_account_id();
+		let (farm, mut stake) = self.settle_farm_stake(farm_id, &account_id)?;
+		let amount = stake.unclaimed;
+		if amount.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		stake.unclaimed = U128(0);
+		self.save_farm_stake(&farm, &account_id, &stake);
+		self.sub_escrow(&farm.reward_token_id, amount.0);
+		self.add_pending_transfer(&farm.reward_token_id, amount.0);
+
+		Ok(ext_ft::ext(farm.reward_token_id)
+			.with_attached_deposit(1)
+			.with_static_gas(Gas(5 * TGAS))
+			.ft_transfer(account_id.clone(), amount, None)
+			.then(
+				ext_self::ext(env::current_account_id())
+					.with_static_gas(Gas(5 * TGAS))
+					.on_farm_rewards_claimed(farm_id, account_id, amount),
+			))
+	}
+
+	// Restores the claimed rewards if the transfer failed
+	#[private]
+	pub fn on_farm_rewards_claimed(&mut self, farm_id: u64, account_id: AccountId, amount: U128) {
+		let reward_token_id = self.get_farm(farm_id).unwrap().reward_token_id;
+		self.sub_pending_transfer(&reward_token_id, amount.0);
+		if let PromiseResult::Failed = env::promise_result(0) {
+			let key = (farm_id, account_id);
+			let mut stake = self.farming.stakes.get(&key).unwrap_or_default();
+			stake.unclaimed.0 += amount.0;
+			self.farming.stakes.insert(&key, &stake);
+			let farm = self.get_farm(farm_id).unwrap();
+			self.add_escrow(&farm.reward_token_id, amount.0);
+			log!("Claiming {} rewards of farm {} failed, restored to {}", amount.0, farm_id, key.1);
+		}
+	}
+
+	// Sends the rewards nobody earned because nothing was staked to the owner's wallet
+	#[payable]
+	#[handle_result]
+	pub fn reclaim_unallocated_rewards(&mut self, farm_id: u64) -> Result<Promise, AmmError> {
+		assert_one_yocto();
+		self.assert_owner()?;
+		let mut farm = self.get_farm(farm_id).ok_or(AmmError::FarmNotFound(farm_id))?;
+		farm.update(env::block_timestamp())?;
+		let amount = farm.unallocated;
+		if amount.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		farm.unallocated = U128(0);
+		self.farming.farms.replace(farm_id, &farm);
+		self.sub_escrow(&farm.reward_token_id, amount.0);
+		self.add_pending_transfer(&farm.reward_token_id, amount.0);
+
+		Ok(ext_ft::ext(farm.reward_token_id)
+			.with_attached_deposit(1)
+			.with_static_gas(Gas(5 * TGAS))
+			.ft_transfer(self.owner_id.clone(), amount, None)
+			.then(
+				ext_self::ext(env::current_account_id())
+					.with_static_gas(Gas(5 * TGAS))
+					.on_unallocated_rewards_reclaimed(farm_id, amount),
+			))
+	}
+
+	// Restores the reclaimed rewards if the transfer failed
+	#[private]
+	pub fn on_unallocated_rewards_reclaimed(&mut self, farm_id: u64, amount: U128) {
+		let mut farm = self.get_farm(farm_id).unwrap();
+		self.sub_pending_transfer(&farm.reward_token_id, amount.0);
+		if let PromiseResult::Failed = env::promise_result(0) {
+			farm.unallocated.0 += amount.0;
+			self.farming.farms.replace(farm_id, &farm);
+			self.add_escrow(&farm.reward_token_id, amount.0);
+			log!("Reclaiming {} rewards of farm {} failed, restored", amount.0, farm_id);
+		}
+	}
+
+	pub fn get_farm(&self, farm_id: u64) -> Option<Farm> {
+		self.farming.farms.get(farm_id)
+	}
+
+	pub fn get_farms(&self, from: Option<u64>, limit: Option<u64>) -> Vec<Farm> {
+		let from = from.unwrap_or(0);
+		let to = self
+			.farming
+			.farms
+			.len()
+			.min(from.saturating_add(limit.unwrap_or(DEFAULT_PAGE_LIMIT)));
+		(from..to).filter_map(|farm_id| self.farming.farms.get(farm_id)).collect()
+	}
+
+	// Stake with the rewards earned up to now
+	#[handle_result]
+	pub fn get_farm_stake(
+		&self,
+		farm_id: u64,
+		account_id: AccountId,
+	) -> Result<FarmStake, AmmError> {
+		let mut farm = self.get_farm(farm_id).ok_or(AmmError::FarmNotFound(farm_id))?;
+		farm.update(env::block_timestamp())?;
+		let mut stake = self.farming.stakes.get(&(farm_id, account_id)).unwrap_or_default();
+		stake.settle(&farm)?;
+		Ok(stake)
+	}
+}
+
+impl Contract {
+	// Credits rewards sent with ft_transfer_call, they stream over the rest of the farm
+	pub(crate) fn internal_fund_farm(
+		&mut self,
+		farm_id: u64,
+		token_id: &AccountId,
+		amount: u128,
+	) -> Result<(), AmmError> {
+		let mut farm = self.get_farm(farm_id).ok_or(AmmError::FarmNotFound(farm_id))?;
+		if farm.reward_token_id != *token_id {
+			return Err(AmmError::TokenNotSupported(token_id.clone()))
+		}
+		let now = env::block_timestamp();
+		if now >= farm.end_at.0 {
+			return Err(AmmError::InvalidFarm)
+		}
+		farm.update(now)?;
+		if now > farm.start_at.0 {
+			// Restart the stream of what is left from now, so the new rewards aren't released at
+			// once
+			farm.total_reward = U128(farm.total_reward.0 - farm.released.0 + amount);
+			farm.start_at = U64(now);
+			farm.released = U128(0);
+		} else {
+			farm.total_reward.0 += amount;
+		}
+		self.farming.farms.replace(farm_id, &farm);
+		self.add_escrow(token_id, amount);
+		log!("Farm {} funded with {} of {}", farm_id, amount, token_id);
+		Ok(())
+	}
+
+	fn settle_farm_stake(
+		&mut self,
+		farm_id: u64,
+		account_id: &AccountId,
+	) -> Result<(Farm, FarmStake), AmmError> {
+		let mut farm = self.get_farm(farm_id).ok_or(AmmError::FarmNotFound(farm_id))?;
+		farm.update(env::block_timestamp())?;
+		let mut stake = self.farming.stakes.get(&(farm_id, account_id.clone())).unwrap_or_default();
+		stake.settle(&farm)?;
+		Ok((farm, stake))
+	}
+
+	fn save_farm_stake(&mut self, farm: &Farm, account_id: &AccountId, stake: &FarmStake) {
+		self.farming.farms.replace(farm.farm_id, farm);
+		let key = (farm.farm_id, account_id.clone());
+		if stake.shares.0 == 0 && stake.unclaimed.0 == 0 {
+			self.farming.stakes.remove(&key);
+		} else {
+			self.farming.stakes.insert(&key, stake);
+		}
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env, RuntimeFeesConfig, VMConfig};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_farm_rewards() {
+		let (mut context, mut contract) = setup_pool();
+		let reward_token_id = accounts(5);
+		let second = NANOS_PER_SECOND;
+		let farm_id = contract
+			.create_farm(
+				DEFAULT_POOL_ID,
+				reward_token_id.clone(),
+				U64(10 * second),
+				U64(110 * second),
+			)
+			.unwrap();
+		assert_eq!(
+			contract.internal_fund_farm(farm_id, &token_a(), 3_000),
+			Err(AmmError::TokenNotSupported(token_a()))
+		);
+		contract.internal_fund_farm(farm_id, &reward_token_id, 3_000).unwrap();
+
+		// The owner holds all the shares, stakes half of them before the start
+		let shares = contract.ft_balance_of(env::current_account_id(), accounts(1)).unwrap().0;
+		contract.stake_lp(farm_id, U128(shares / 2)).unwrap();
+		assert_eq!(
+			contract.ft_balance_of(env::current_account_id(), accounts(1)).unwrap(),
+			U128(shares - shares / 2)
+		);
+
+		// 10% of the stream has passed
+		testing_env!(context.block_timestamp(20 * second).build());
+		assert_eq!(contract.get_farm_stake(farm_id, accounts(1)).unwrap().unclaimed, U128(300));
+
+		// A second staker with the same amount halves the rate
+		contract.token_lp.internal_register_account(&accounts(2));
+		contract.add_token_holder(&env::current_account_id(), &accounts(2));
+		contract
+			.token_lp
+			.internal_transfer(&accounts(1), &accounts(2), shares / 2, None);
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		contract.stake_lp(farm_id, U128(shares / 2)).unwrap();
+		testing_env!(context.block_timestamp(120 * second).build());
+		assert_eq!(contract.get_farm_stake(farm_id, accounts(2)).unwrap().unclaimed, U128(1_350));
+		assert_eq!(contract.get_farm_stake(farm_id, accounts(1)).unwrap().unclaimed, U128(1_650));
+
+		contract.unstake_lp(farm_id, U128(shares / 2)).unwrap();
+		assert_eq!(
+			contract.ft_balance_of(env::current_account_id(), accounts(2)).unwrap(),
+			U128(shares / 2)
+		);
+		assert_eq!(contract.get_farm(farm_id).unwrap().total_staked, U128(shares / 2));
+		testing_env!(context.attached_deposit(1).build());
+		contract.claim_farm_rewards(farm_id).unwrap();
+		let stake = contract.get_farm_stake(farm_id, accounts(2)).unwrap();
+		assert_eq!((stake.shares, stake.unclaimed), (U128(0), U128(0)));
+	}
+
+	#[test]
+	fn test_reclaim_unallocated_rewards() {
+		let (mut context, mut contract) = setup_pool();
+		let reward_token_id = accounts(5);
+		let second = NANOS_PER_SECOND;
+		let farm_id = contract
+			.create_farm(DEFAULT_POOL_ID, reward_token_id.clone(), U64(0), U64(100 * second))
+			.unwrap();
+		contract.internal_fund_farm(farm_id, &reward_token_id, 1_000).unwrap();
+
+		// Nothing is staked during the first 20% of the stream
+		testing_env!(context.block_timestamp(20 * second).build());
+		let shares = contract.ft_balance_of(env::current_account_id(), accounts(1)).unwrap().0;
+		contract.stake_lp(farm_id, U128(shares)).unwrap();
+		testing_env!(context.block_timestamp(100 * second).build());
+		// The staker earns the remaining 800, rounded down
+		assert_eq!(contract.get_farm_stake(farm_id, accounts(1)).unwrap().unclaimed, U128(799));
+
+		testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+		assert!(matches!(contract.reclaim_unallocated_rewards(farm_id), Err(AmmError::NotOwner)));
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		assert!(contract.reclaim_unallocated_rewards(farm_id).is_ok());
+		assert_eq!(contract.get_farm(farm_id).unwrap().unallocated, U128(0));
+		assert_eq!(contract.escrowed.get(&reward_token_id), Some(800));
+
+		// A failed transfer makes them reclaimable again
+		testing_env!(
+			context.predecessor_account_id(accounts(0)).build(),
+			VMConfig::test(),
+			RuntimeFeesConfig::test(),
+			Default::default(),
+			vec![PromiseResult::Failed],
+		);
+		contract.on_unallocated_rewards_reclaimed(farm_id, U128(200));
+		assert_eq!(contract.get_farm(farm_id).unwrap().unallocated, U128(200));
+		assert_eq!(contract.escrowed.get(&reward_token_id), Some(1_000));
+	}
+}
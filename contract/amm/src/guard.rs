@@ -0,0 +1,81 @@
+use near_sdk::{
+	env,
+	json_types::{U128, U64},
+	serde::{Deserialize, Serialize},
+};
+
+use crate::*;
+
+// Limits on when and against which pool state a transaction may still execute, so one that sat
+// in the mempool doesn't run against a market that moved. Complements the slippage limits.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TradeGuard {
+	// Latest block timestamp in ns the transaction may execute at
+	pub deadline: Option<U64>,
+	// (token_a, token_b) reserves of the pool the transaction was signed against
+	pub expected_reserves: Option<(U128, U128)>,
+	// Highest accepted deviation of the pool price from the price of expected_reserves, 0 if unset
+	pub max_price_deviation_bps: Option<u32>,
+}
+
+impl Contract {
+	pub(crate) fn assert_trade_guard(&self, guard: &Option<TradeGuard>) -> Result<(), AmmError> {
+		let guard = match guard {
+			Some(guard) => guard,
+			None => return Ok(()),
+		};
+		if let Some(deadline) = guard.deadline {
+			if env::block_timestamp() > deadline.0 {
+				return Err(AmmError::DeadlineExceeded(deadline.0))
+			}
+		}
+		if let Some((expected_a, expected_b)) = guard.expected_reserves {
+			let (reserve_a, reserve_b) = self.pool_reserves()?;
+			let deviation_bps = price_deviation_bps(
+				calc_price(reserve_a, reserve_b)?,
+				calc_price(expected_a.0, expected_b.0)?,
+			)?;
+			if deviation_bps > guard.max_price_deviation_bps.unwrap_or(0) as u128 {
+				return Err(AmmError::PriceDeviationExceeded(deviation_bps as u32))
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_trade_guard() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 10_000);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+		testing_env!(context.predecessor_account_id(accounts(2)).block_timestamp(100).build());
+
+		let expired = TradeGuard { deadline: Some(U64(99)), ..Default::default() };
+		assert_eq!(
+			contract.swap(token_b(), token_a(), U128(1_000), None, Some(expired)),
+			Err(AmmError::DeadlineExceeded(99))
+		);
+
+		// The price moved 1% since the transaction was signed
+		let moved = TradeGuard {
+			deadline: Some(U64(100)),
+			expected_reserves: Some((U128(POOL_A), U128(POOL_B * 101 / 100))),
+			max_price_deviation_bps: Some(50),
+		};
+		assert_eq!(
+			contract.swap(token_b(), token_a(), U128(1_000), None, Some(moved.clone())),
+			Err(AmmError::PriceDeviationExceeded(99))
+		);
+		let tolerant = TradeGuard { max_price_deviation_bps: Some(100), ..moved };
+		assert!(contract.swap(token_b(), token_a(), U128(1_000), None, Some(tolerant)).is_ok());
+	}
+}
@@ -0,0 +1,229 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	collections::LookupMap,
+	env,
+	json_types::{Base64VecU8, U128, U64},
+	log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	serde_json, AccountId, CurveType, PublicKey,
+};
+
+use crate::*;
+
+// Keys an account can sign intents with
+pub const MAX_INTENT_KEYS: usize = 10;
+
+// Swap signed off-chain by the owner of account_id. The signature covers the exact JSON string
+// submitted to `execute_intent`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapIntent {
+	// The AMM the intent is meant for, so it can't be replayed on another deployment
+	pub contract_id: AccountId,
+	pub account_id: AccountId,
+	pub pool_id: u64,
+	pub sell_token_id: AccountId,
+	pub buy_token_id: AccountId,
+	pub sell_amount: U128,
+	pub min_amount_out: U128,
+	// Paid in sell_token to the solver submitting the intent
+	pub solver_tip: U128,
+	// Must be above the last nonce used by the account
+	pub nonce: U64,
+	// Latest block timestamp in ns the intent may execute at
+	pub deadline: U64,
+}
+
+// Off-chain swap intents. Accounts register ed25519 keys, any solver can submit an intent
+// signed by one of them and the swap is settled against the internal balance of the account.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Intents {
+	// account_id:keys allowed to sign intents of the account
+	pub keys: LookupMap<AccountId, Vec<PublicKey>>,
+	// account_id:last used nonce
+	pub nonces: LookupMap<AccountId, u64>,
+}
+
+impl Intents {
+	pub fn new() -> Self {
+		Self { keys: LookupMap::new(b"ikeys".to_vec()), nonces: LookupMap::new(b"inonce".to_vec()) }
+	}
+}
+
+impl Default for Intents {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	#[handle_result]
+	pub fn add_intent_key(&mut self, public_key: PublicKey) -> Result<(), AmmError> {
+		if public_key.curve_type() != CurveType::ED25519 {
+			return Err(AmmError::InvalidIntentKey)
+		}
+		let account_id = env::predecessor_account_id();
+		let mut keys = self.get_intent_keys(account_id.clone());
+		if keys.contains(&public_key) || keys.len() >= MAX_INTENT_KEYS {
+			return Err(AmmError::InvalidIntentKey)
+		}
+		keys.push(public_key);
+		self.intents.keys.insert(&account_id, &keys);
+		Ok(())
+	}
+
+	#[handle_result]
+	pub fn remove_intent_key(&mut self, public_key: PublicKey) -> Result<(), AmmError> {
+		let account_id = env::predecessor_account_id();
+		let mut keys = self.get_intent_keys(account_id.clone());
+		let index = keys
+			.iter()
+			.position(|key| *key == public_key)
+			.ok_or(AmmError::InvalidIntentKey)?;
+		keys.swap_remove(index);
+		self.intents.keys.insert(&account_id, &keys);
+		Ok(())
+	}
+
+	// Invalidates every signed intent of the caller up to the nonce
+	pub fn cancel_intents(&mut self, nonce: U64) {
+		let account_id = env::predecessor_account_id();
+		if nonce.0 > self.get_intent_nonce(account_id.clone()).0 {
+			self.intents.nonces.insert(&account_id, &nonce.0);
+		}
+	}
+
+	// Settles a signed intent, the solver tip is credited to the internal balance of the caller
+	#[handle_result]
+	pub fn execute_intent(
+		&mut self,
+		intent: String,
+		signature: Base64VecU8,
+	) -> Result<U128, AmmError> {
+		let message = intent;
+		let intent: SwapIntent =
+			serde_json::from_str(&message).map_err(|_| AmmError::InvalidIntent)?;
+		if intent.contract_id != env::current_account_id() {
+			return Err(AmmError::InvalidIntent)
+		}
+		if env::block_timestamp() > intent.deadline.0 {
+			return Err(AmmError::DeadlineExceeded(intent.deadline.0))
+		}
+		let signature: [u8; 64] = signature.0.try_into().map_err(|_| AmmError::InvalidSignature)?;
+		let signed = self.get_intent_keys(intent.account_id.clone()).iter().any(|key| {
+			// Skip the curve type byte
+			ed25519_verify(&signature, message.as_bytes(), key.as_bytes()[1..].try_into().unwrap())
+		});
+		if !signed {
+			return Err(AmmError::InvalidSignature)
+		}
+		if intent.nonce.0 <= self.get_intent_nonce(intent.account_id.clone()).0 {
+			return Err(AmmError::NonceUsed(intent.nonce.0))
+		}
+		self.intents.nonces.insert(&intent.account_id, &intent.nonce.0);
+
+		let amount_out = self.internal_swap_tokens(
+			&intent.account_id,
+			intent.pool_id,
+			intent.buy_token_id,
+			intent.sell_token_id.clone(),
+			intent.sell_amount,
+			None,
+		)?;
+		if amount_out.0 < intent.min_amount_out.0 {
+			return Err(AmmError::SlippageExceeded)
+		}
+		if intent.solver_tip.0 > 0 {
+			let solver_id = env::predecessor_account_id();
+			let mut token = self.get_token(&intent.sell_token_id)?;
+			internal_balance_of(&token, &solver_id)?;
+			if internal_balance_of(&token, &intent.account_id)? < intent.solver_tip.0 {
+				return Err(AmmError::NotEnoughBalance)
+			}
+			token.internal_transfer(&intent.account_id, &solver_id, intent.solver_tip.0, None);
+			self.tokens.insert(&intent.sell_token_id, &token);
+		}
+		log!("Intent {} of {} executed", intent.nonce.0, intent.account_id);
+		Ok(amount_out)
+	}
+
+	pub fn get_intent_keys(&self, account_id: AccountId) -> Vec<PublicKey> {
+		self.intents.keys.get(&account_id).unwrap_or_default()
+	}
+
+	pub fn get_intent_nonce(&self, account_id: AccountId) -> U64 {
+		U64(self.intents.nonces.get(&account_id).unwrap_or(0))
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use ed25519_dalek::{Keypair, SecretKey, Signer};
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	fn keypair() -> Keypair {
+		let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+		Keypair { public: (&secret).into(), secret }
+	}
+
+	fn sign(intent: &SwapIntent) -> (String, Base64VecU8) {
+		let message = serde_json::to_string(intent).unwrap();
+		let signature = keypair().sign(message.as_bytes());
+		(message, Base64VecU8(signature.to_bytes().to_vec()))
+	}
+
+	#[test]
+	fn test_execute_intent() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 1_010);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+		deposit(&mut contract, &token_a(), &accounts(5), 0);
+
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		let mut public_key = vec![CurveType::ED25519 as u8];
+		public_key.extend(keypair().public.as_bytes());
+		contract.add_intent_key(PublicKey::try_from(public_key).unwrap()).unwrap();
+
+		let amount_out = calc_dy(POOL_A, POOL_B, 1_000).unwrap();
+		let intent = SwapIntent {
+			contract_id: accounts(0),
+			account_id: accounts(2),
+			pool_id: DEFAULT_POOL_ID,
+			sell_token_id: token_a(),
+			buy_token_id: token_b(),
+			sell_amount: U128(1_000),
+			min_amount_out: U128(amount_out),
+			solver_tip: U128(10),
+			nonce: U64(1),
+			deadline: U64(100),
+		};
+		let (message, signature) = sign(&intent);
+
+		// Submitted by a solver
+		testing_env!(context.predecessor_account_id(accounts(5)).build());
+		let forged = message.replace("1000", "1010");
+		assert_eq!(
+			contract.execute_intent(forged, signature.clone()),
+			Err(AmmError::InvalidSignature)
+		);
+		assert_eq!(
+			contract.execute_intent(message.clone(), signature.clone()).unwrap().0,
+			amount_out
+		);
+		assert_eq!(contract.ft_balance_of(token_b(), accounts(2)).unwrap(), U128(amount_out));
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(5)).unwrap(), U128(10));
+		assert_eq!(contract.execute_intent(message, signature), Err(AmmError::NonceUsed(1)));
+
+		let (message, signature) = sign(&SwapIntent { nonce: U64(2), ..intent });
+		testing_env!(context.block_timestamp(101).build());
+		assert_eq!(
+			contract.execute_intent(message, signature),
+			Err(AmmError::DeadlineExceeded(100))
+		);
+	}
+}
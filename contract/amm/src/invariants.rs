@@ -0,0 +1,329 @@
+use near_sdk::{
+	collections::Vector,
+	env,
+	json_types::U128,
+	near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId,
+};
+
+use crate::*;
+
+// Holders of a ledger the full check sums, larger ledgers are left to
+// `get_ledger_reconciliation`
+pub const MAX_RECONCILED_HOLDERS: u64 = 100;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InvariantReport {
+	// Ledgers whose balances weren't summed: created before V3, so their holders can't be
+	// listed, or with more than MAX_RECONCILED_HOLDERS holders
+	pub unreconciled: Vec<AccountId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LedgerReconciliation {
+	pub token_id: AccountId,
+	// Indexed holders of the ledger
+	pub holders: u64,
+	// Sum of the balances of the requested page of holders
+	pub balances: U128,
+	pub total_supply: U128,
+}
+
+// Defense in depth against accounting bugs. Internal balances live in maps that can't be
+// iterated, so the checks sum them through the index of registered holders, and reconcile the
+// totals the AMM tracks on top of the ledgers against the positions that can be enumerated.
+// The cheap checks run after every state change, the full ones only in debug builds.
+#[near_bindgen]
+impl Contract {
+	// Runs every check, fails with the first violated invariant. Lists the ledgers whose balances
+	// couldn't be summed.
+	#[handle_result]
+	pub fn check_invariants(&self) -> Result<InvariantReport, AmmError> {
+		self.check_cheap_invariants()?;
+		let unreconciled = self.check_full_invariants()?;
+		Ok(InvariantReport { unreconciled })
+	}
+
+	// Sums the balances of a page of the holders of a ledger, the LP ledger under the AMM id.
+	// Adding the pages up at the same block reconciles ledgers too large for `check_invariants`.
+	#[handle_result]
+	pub fn get_ledger_reconciliation(
+		&self,
+		token_id: AccountId,
+		from: Option<u64>,
+		limit: Option<u64>,
+	) -> Result<LedgerReconciliation, AmmError> {
+		let holders = self
+			.token_holders
+			.get(&token_id)
+			.ok_or_else(|| AmmError::LedgerNotIndexed(token_id.clone()))?;
+		let from = from.unwrap_or(0);
+		let to = holders.len().min(from.saturating_add(limit.unwrap_or(DEFAULT_PAGE_LIMIT)));
+		let (balances, total_supply) = if token_id == env::current_account_id() {
+			(sum_balances(&self.token_lp, &holders, from, to)?, self.token_lp.total_supply)
+		} else {
+			let token = self.get_token(&token_id)?;
+			(sum_balances(&token, &holders, from, to)?, token.total_supply)
+		};
+		Ok(LedgerReconciliation {
+			token_id,
+			holders: holders.len(),
+			balances: U128(balances),
+			total_supply: U128(total_supply),
+		})
+	}
+}
+
+impl Contract {
+	pub(crate) fn assert_invariants(&self) -> Result<(), AmmError> {
+		self.check_cheap_invariants()?;
+		if cfg!(debug_assertions) {
+			self.check_full_invariants()?;
+		}
+		Ok(())
+	}
+
+	// Indexes an account registered in the ledger of token_id, or the LP ledger for the AMM id
+	pub(crate) fn add_token_holder(&mut self, token_id: &AccountId, account_id: &AccountId) {
+		if let Some(mut holders) = self.token_holders.get(token_id) {
+			holders.push(account_id);
+			self.token_holders.insert(token_id, &holders);
+		}
+	}
+
+	// LP supply is zero iff the pool is empty. Reads the ledgers directly, so it only fails on
+	// broken accounting.
+	fn check_cheap_invariants(&self) -> Result<(), AmmError> {
+		let pool_owner_id = env::current_account_id();
+		let reserve = |token_id: &AccountId| {
+			self.tokens
+				.get(token_id)
+				.and_then(|token| token.accounts.get(&pool_owner_id))
+				.unwrap_or(0)
+		};
+		let (reserve_a, reserve_b) =
+			(reserve(&self.token_a_contract), reserve(&self.token_b_contract));
+		if (self.token_lp.total_supply == 0) != (reserve_a == 0 && reserve_b == 0) {
+			return Err(AmmError::InvariantViolated(format!(
+				"LP supply {} with reserves ({}, {})",
+				self.token_lp.total_supply, reserve_a, reserve_b
+			)))
+		}
+		Ok(())
+	}
+
+	// Internal balances add up to the supply of their ledger, escrowed totals cover the open
+	// orders, batches and multi-asset pools, staked LP shares match the farms. Returns the
+	// ledgers which weren't reconciled.
+	fn check_full_invariants(&self) -> Result<Vec<AccountId>, AmmError> {
+		let mut unreconciled = vec![];
+		let lp_id = env::current_account_id();
+		if !self.reconcile_ledger(&lp_id, &self.token_lp)? {
+			unreconciled.push(lp_id);
+		}
+		for token_id in self.token_ids() {
+			if !self.reconcile_ledger(&token_id, &self.get_token(&token_id)?)? {
+				unreconciled.push(token_id);
+			}
+		}
+
+		let mut positions: Vec<(AccountId, u128)> = vec![];
+		let mut add = |token_id: &AccountId, amount: u128| match positions
+			.iter_mut()
+			.find(|(id, _)| id == token_id)
+		{
+			Some((_, total)) => *total += amount,
+			None => positions.push((token_id.clone(), amount)),
+		};
+		for order in self.order_book.orders.values() {
+			add(&order.sell_token_id, order.amount.0);
+			add(&order.buy_token_id, order.filled.0);
+		}
+		for order in self.dca_orders.orders.values() {
+			add(&order.sell_token_id, order.escrow()?);
+		}
+		for commitment in self.commitments.commitments.values() {
+			add(&commitment.token_id, commitment.amount.0);
+		}
+		for batch in self.batch_auctions.batches.values() {
+			let pool = self.get_pool(batch.pool_id)?;
+			for (token_id, amount) in pool.token_ids.iter().zip(batch.unclaimed.iter()) {
+				add(token_id, amount.0);
+			}
+		}
+		for pool in self.multi_pools.pools.iter() {
+			for (token_id, balance) in pool.token_ids.iter().zip(pool.balances.iter()) {
+				add(token_id, balance.0);
+			}
+		}
+		// Farm rewards are escrowed as well, so the orders are a lower bound
+		for (token_id, amount) in positions {
+			let escrowed = self.escrowed.get(&token_id).unwrap_or(0);
+			if escrowed < amount {
+				return Err(AmmError::InvariantViolated(format!(
+					"{} of {} escrowed for orders of {}",
+					escrowed, token_id, amount
+				)))
+			}
+		}
+
+		let staked: u128 = self.farming.farms.iter().map(|farm| farm.total_staked.0).sum();
+		let held = self.token_lp.accounts.get(&env::current_account_id()).unwrap_or(0);
+		if staked != held {
+			return Err(AmmError::InvariantViolated(format!(
+				"{} LP shares held for {} staked",
+				held, staked
+			)))
+		}
+		Ok(unreconciled)
+	}
+}
+
+impl Contract {
+	// False if the ledger has no index or is too large to sum
+	fn reconcile_ledger(
+		&self,
+		token_id: &AccountId,
+		token: &FungibleToken,
+	) -> Result<bool, AmmError> {
+		let holders = match self.token_holders.get(token_id) {
+			Some(holders) if holders.len() <= MAX_RECONCILED_HOLDERS => holders,
+			_ => return Ok(false),
+		};
+		let balances = sum_balances(token, &holders, 0, holders.len())?;
+		if balances != token.total_supply {
+			return Err(AmmError::InvariantViolated(format!(
+				"balances of {} sum to {} for a supply of {}",
+				token_id, balances, token.total_supply
+			)))
+		}
+		Ok(true)
+	}
+}
+
+fn sum_balances(
+	token: &FungibleToken,
+	holders: &Vector<AccountId>,
+	from: u64,
+	to: u64,
+) -> Result<u128, AmmError> {
+	let mut balances: u128 = 0;
+	for index in from..to {
+		let account_id = holders.get(index).unwrap();
+		balances = balances
+			.checked_add(token.accounts.get(&account_id).unwrap_or(0))
+			.ok_or(AmmError::MathOverflow)?;
+	}
+	Ok(balances)
+}
+
+// Index of the holders of a new ledger
+pub fn holders_index(token_id: &AccountId, holders: &[&AccountId]) -> Vector<AccountId> {
+	let mut index = Vector::new([b"th:".as_slice(), token_id.as_bytes()].concat());
+	for account_id in holders {
+		index.push(*account_id);
+	}
+	index
+}
+
+// The fee stays in the pool, so a swap can only grow the product of the reserves
+pub fn assert_k_not_decreased(before: (u128, u128), after: (u128, u128)) -> Result<(), AmmError> {
+	if U256::from(after.0) * U256::from(after.1) < U256::from(before.0) * U256::from(before.1) {
+		return Err(AmmError::InvariantViolated(format!(
+			"k decreased from {:?} to {:?}",
+			before, after
+		)))
+	}
+	Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+	use near_sdk::{
+		json_types::U128,
+		test_utils::{accounts, VMContextBuilder},
+		testing_env, PromiseOrValue,
+	};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_invariants() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 1_000);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		contract.swap(token_b(), token_a(), U128(500), None, None).unwrap();
+		contract
+			.place_limit_order(
+				DEFAULT_POOL_ID,
+				token_a(),
+				token_b(),
+				U128(400),
+				U128(PRICE_PRECISION),
+			)
+			.unwrap();
+		assert_eq!(contract.check_invariants(), Ok(InvariantReport { unreconciled: vec![] }));
+
+		contract.escrowed.insert(&token_a(), &399);
+		assert!(matches!(contract.check_invariants(), Err(AmmError::InvariantViolated(_))));
+		contract.escrowed.insert(&token_a(), &400);
+
+		// A balance credited outside of the ledger methods
+		let mut token = contract.get_token(&token_b()).unwrap();
+		token.accounts.insert(&accounts(2), &1);
+		contract.tokens.insert(&token_b(), &token);
+		assert!(matches!(contract.check_invariants(), Err(AmmError::InvariantViolated(_))));
+		token.accounts.insert(&accounts(2), &0);
+		contract.tokens.insert(&token_b(), &token);
+
+		// Pages of holders add up to the supply
+		let page = |from, limit| {
+			contract.get_ledger_reconciliation(token_a(), Some(from), Some(limit)).unwrap()
+		};
+		let (first, rest) = (page(0, 2), page(2, 100));
+		assert_eq!(first.holders, 3);
+		assert_eq!(first.balances.0 + rest.balances.0, first.total_supply.0);
+
+		// Ledgers of a migrated contract have no index and are reported
+		contract.token_holders.remove(&token_b());
+		let report = contract.check_invariants().unwrap();
+		assert_eq!(report.unreconciled, vec![token_b()]);
+		assert_eq!(
+			contract.get_ledger_reconciliation(token_b(), None, None),
+			Err(AmmError::LedgerNotIndexed(token_b()))
+		);
+
+		// Reserves left behind after all shares are gone
+		contract.token_lp.total_supply = 0;
+		assert!(matches!(contract.check_invariants(), Err(AmmError::InvariantViolated(_))));
+	}
+
+	#[test]
+	fn test_deposit_before_amm_registration() {
+		let mut context = VMContextBuilder::new();
+		context.current_account_id(accounts(0)).predecessor_account_id(accounts(0));
+		testing_env!(context.build());
+		let mut contract = Contract::new(accounts(1), token_a(), token_b());
+		contract.on_ft_metadata(token_a(), metadata("FTA", 4)).unwrap();
+		deposit(&mut contract, &token_a(), &accounts(2), 0);
+
+		testing_env!(context.predecessor_account_id(token_a()).build());
+		let refund = contract.ft_on_transfer(accounts(2), U128(100), "".to_string());
+		assert!(matches!(refund, PromiseOrValue::Value(U128(0))));
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(100));
+		assert_eq!(contract.check_invariants(), Ok(InvariantReport { unreconciled: vec![] }));
+	}
+
+	#[test]
+	fn test_k_not_decreased() {
+		assert_eq!(assert_k_not_decreased((100, 100), (110, 91)), Ok(()));
+		assert!(assert_k_not_decreased((100, 100), (110, 90)).is_err());
+	}
+}
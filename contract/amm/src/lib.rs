@@ -1,36 +1,112 @@
 use std::cmp::max;
 
 use near_contract_standards::{
-	fungible_token::{
-		core::FungibleTokenCore, metadata::FungibleTokenMetadata, receiver::FungibleTokenReceiver,
-		FungibleToken,
-	},
+	fungible_token::{core::FungibleTokenCore, metadata::FungibleTokenMetadata, FungibleToken},
 	storage_management::{StorageBalance, StorageBalanceBounds, StorageManagement},
 };
 use near_sdk::{
 	assert_self,
 	borsh::{self, BorshDeserialize, BorshSerialize},
-	collections::LookupMap,
+	collections::{LookupMap, UnorderedMap, UnorderedSet, Vector},
 	env,
 	json_types::U128,
 	log, near_bindgen,
 	serde::{Deserialize, Serialize},
-	AccountId, Gas, PanicOnDefault, PromiseOrValue, PromiseResult,
+	AccountId, FunctionError, Gas, PanicOnDefault, PromiseResult,
 };
 
+mod actions;
+pub use crate::actions::*;
+
+mod batch_auction;
+pub use crate::batch_auction::*;
+
+mod commit_reveal;
+pub use crate::commit_reveal::*;
+
+mod dca;
+pub use crate::dca::*;
+
+mod errors;
+pub use crate::errors::*;
+
+mod events;
+pub use crate::events::*;
+
 mod external;
 pub use crate::external::*;
 
+mod farm;
+pub use crate::farm::*;
+
+mod guard;
+pub use crate::guard::*;
+
+mod intent;
+pub use crate::intent::*;
+
+mod invariants;
+pub use crate::invariants::*;
+
+mod oracle;
+pub use crate::oracle::*;
+
+mod multi_pool;
+pub use crate::multi_pool::*;
+
+mod order_book;
+pub use crate::order_book::*;
+
+mod owner;
+pub use crate::owner::*;
+
+mod pause;
+pub use crate::pause::*;
+
+mod pool;
+pub use crate::pool::*;
+
+mod position;
+pub use crate::position::*;
+
+mod receiver;
+pub use crate::receiver::*;
+
+mod referral;
+pub use crate::referral::*;
+
+mod sync;
+pub use crate::sync::*;
+
+mod upgrade;
+pub use crate::upgrade::*;
+
+mod whitelist;
+
+mod wrap;
+
+mod zap;
+
 mod utils;
 pub use crate::utils::*;
 
+mod views;
+pub use crate::views::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod test_utils;
+
+// Identifier of the token_a/token_b pool
+pub const DEFAULT_POOL_ID: u64 = 0;
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
 	// Contract Owner
 	pub owner_id: AccountId,
 
-	// Token A and Token B token_contract:token_interface
+	// Whitelisted token_contract:token_interface
 	pub tokens: LookupMap<AccountId, FungibleToken>,
 
 	// token_contract:token_metadata
@@ -47,6 +123,67 @@ pub struct Contract {
 
 	// Token ratio in the pool (token_a,token_b)
 	pub token_ratio: (U128, U128),
+
+	// Account proposed by the owner, which has to accept the ownership
+	pub pending_owner_id: Option<AccountId>,
+
+	// account_id:granted_roles
+	pub roles: UnorderedMap<AccountId, Vec<Role>>,
+
+	// Actions paused for all pools
+	pub paused: PauseStatus,
+
+	// pool_id:paused_actions
+	pub paused_pools: LookupMap<u64, PauseStatus>,
+
+	// Halts swaps on abnormal price moves, disabled if None
+	pub circuit_breaker: Option<CircuitBreaker>,
+
+	// Pool settings and statistics, pool_id is the index
+	pub pools: Vector<Pool>,
+
+	// wNEAR token contract used for native NEAR deposits and withdrawals
+	pub wrap_near_id: Option<AccountId>,
+
+	// Limit orders resting against the pools
+	pub order_book: OrderBook,
+
+	// Dollar cost averaging orders executed by keepers
+	pub dca_orders: DcaOrders,
+
+	// LP staking farms
+	pub farming: Farming,
+
+	// Approved referrers and their earnings
+	pub referrals: Referrals,
+
+	// Token contracts which can be deposited and used in pools
+	pub whitelisted_tokens: UnorderedSet<AccountId>,
+
+	// token_contract:amount held outside internal balances, e.g. by orders and farms
+	pub escrowed: LookupMap<AccountId, u128>,
+
+	// token_contract:real AMM balance fetched by `sync`
+	pub synced_balances: LookupMap<AccountId, SyncedBalance>,
+
+	// token_contract:amount of transfers in flight, see `add_pending_transfer`
+	pub pending_transfers: LookupMap<AccountId, u128>,
+
+	pub intents: Intents,
+
+	pub commitments: Commitments,
+
+	pub batch_auctions: BatchAuctions,
+
+	// account_id:LP position in the default pool
+	pub lp_positions: LookupMap<AccountId, LpPosition>,
+
+	// Pools of 2 to MAX_POOL_TOKENS tokens and their shares
+	pub multi_pools: MultiPools,
+
+	// token_id:accounts registered in its ledger, the LP ledger under the AMM account id. Only
+	// ledgers created since V3 are indexed.
+	pub token_holders: LookupMap<AccountId, Vector<AccountId>>,
 }
 
 #[near_bindgen]
@@ -77,7 +214,17 @@ impl Contract {
 		tokens.insert(&token_a_contract, &token_a);
 		tokens.insert(&token_b_contract, &token_b);
 		let token_metadatas = LookupMap::new(b"tokdat".to_vec());
+		let mut whitelisted_tokens = UnorderedSet::new(b"wl".to_vec());
+		whitelisted_tokens.insert(&token_a_contract);
+		whitelisted_tokens.insert(&token_b_contract);
+		let mut token_holders = LookupMap::new(b"th".to_vec());
+		for token_id in [&token_a_contract, &token_b_contract, &env::current_account_id()] {
+			token_holders.insert(token_id, &holders_index(token_id, &[&owner_id]));
+		}
+		let mut pools = Vector::new(b"pools".to_vec());
+		pools.push(&Pool::new(vec![token_a_contract.clone(), token_b_contract.clone()]));
 		let self_contract_id = env::current_account_id();
+		write_state_version();
 
 		ext_ft::ext(token_a_contract.clone()) // External Contract Token instance
 			.ft_metadata() // External Metadata Promise
@@ -101,268 +248,186 @@ impl Contract {
 			token_a_contract,
 			token_b_contract,
 			token_ratio: (U128(1), U128(1)),
+			pending_owner_id: None,
+			roles: UnorderedMap::new(b"roles".to_vec()),
+			paused: PauseStatus::default(),
+			paused_pools: LookupMap::new(b"paused".to_vec()),
+			circuit_breaker: None,
+			pools,
+			wrap_near_id: None,
+			order_book: OrderBook::new(),
+			dca_orders: DcaOrders::new(),
+			farming: Farming::new(),
+			referrals: Referrals::new(),
+			whitelisted_tokens,
+			escrowed: LookupMap::new(b"escrow".to_vec()),
+			synced_balances: LookupMap::new(b"synced".to_vec()),
+			pending_transfers: LookupMap::new(b"pending".to_vec()),
+			intents: Intents::new(),
+			commitments: Commitments::new(),
+			batch_auctions: BatchAuctions::new(),
+			lp_positions: LookupMap::new(b"pos".to_vec()),
+			multi_pools: MultiPools::new(),
+			token_holders,
 		}
 	}
 
+	#[handle_result]
 	pub fn swap(
 		&mut self,
 		buy_token_id: AccountId,
 		sell_token_id: AccountId,
 		sell_amount: U128,
-	) -> U128 {
-		if buy_token_id.eq(&sell_token_id) {
-			panic!("Tokens can't be equal")
-		}
-
-		let mut buy_token = self.tokens.get(&buy_token_id).expect("Buy token not supported");
-		let mut sell_token = self.tokens.get(&sell_token_id).expect("Sell token not supported");
-		let buy_token_meta =
-			self.token_metadatas.get(&buy_token_id).expect("Buy token meta not found");
-		let sell_token_meta =
-			self.token_metadatas.get(&sell_token_id).expect("Sell token meta not found");
-		let pool_owner_id = env::current_account_id();
-		let user_account_id = env::predecessor_account_id();
-
-		// Get current state of pool
-		let x = sell_token.internal_unwrap_balance_of(&pool_owner_id);
-		let y = buy_token.internal_unwrap_balance_of(&pool_owner_id);
-
-		// Send sell_tokens to pool from seller
-		sell_token.internal_transfer(&user_account_id, &pool_owner_id, sell_amount.0, None);
-
-		// Convert to the same decimal
-		let max_decimals = max(buy_token_meta.decimals, sell_token_meta.decimals);
-		let x = add_decimals(x, max_decimals - sell_token_meta.decimals);
-		let y = add_decimals(y, max_decimals - buy_token_meta.decimals);
-
-		// Calc buy amount
-		let buy_amount = calc_dy(x, y, sell_amount.0);
-
-		// Restore decimal
-		let buy_amount = remove_decimals(buy_amount, max_decimals - buy_token_meta.decimals);
-
-		// Send buy value to user buyer
-		buy_token.internal_transfer(&pool_owner_id, &user_account_id, buy_amount, None);
-
-		// Update tokens data in lookup map
-		self.tokens.insert(&buy_token_id, &buy_token);
-		self.tokens.insert(&sell_token_id, &sell_token);
-
-		// update ratio
-		let token_a_amount = self
-			.tokens
-			.get(&self.token_a_contract)
-			.unwrap()
-			.internal_unwrap_balance_of(&pool_owner_id);
-		let token_b_amount = self
-			.tokens
-			.get(&self.token_b_contract)
-			.unwrap()
-			.internal_unwrap_balance_of(&pool_owner_id);
-		self.token_ratio = (U128(token_a_amount), U128(token_b_amount));
-
-		// Return both amount
-		U128::from(buy_amount)
+		referral_id: Option<AccountId>,
+		guard: Option<TradeGuard>,
+	) -> Result<U128, AmmError> {
+		self.assert_trade_guard(&guard)?;
+		let account_id = env::predecessor_account_id();
+		self.internal_swap_tokens(
+			&account_id,
+			DEFAULT_POOL_ID,
+			buy_token_id,
+			sell_token_id,
+			sell_amount,
+			referral_id,
+		)
 	}
 
 	// Adding tokens to the liquidity pool.
 	// Tokens can only be added in proportion to the amount in the pool
+	#[handle_result]
 	pub fn add_tokens_to_pool(
 		&mut self,
 		token_a_name: AccountId,
 		token_a_amount: U128,
 		token_b_name: AccountId,
 		token_b_amount: U128,
-	) {
-		assert!(self.owner_id == env::predecessor_account_id());
-		if token_a_name.eq(&token_b_name) {
-			panic!("Tokens can't be equal")
-		}
-
-		// Get tokens by names
-		let mut token_a = self.tokens.get(&token_a_name).expect("Token A not supported");
-		let mut token_b = self.tokens.get(&token_b_name).expect("Token B not supported");
-		let token_a_meta = self.token_metadatas.get(&token_a_name).expect("Token A meta not found");
-		let token_b_meta = self.token_metadatas.get(&token_b_name).expect("Token B meta not found");
-
-		let pool_owner_id = env::current_account_id();
-		let payer_id = env::predecessor_account_id();
-
-		// Get current state of pool
-		let pool_a_balance = token_a.internal_unwrap_balance_of(&pool_owner_id);
-		let pool_b_balance = token_b.internal_unwrap_balance_of(&pool_owner_id);
-
-		// Сonvert to the same decimal
-		let max_decimals = max(token_a_meta.decimals, token_b_meta.decimals);
-
-		// We can add tokens to the pool only by proportionally increasing them
-		if pool_a_balance * token_b_amount.0 == pool_b_balance * token_a_amount.0 {
-			token_a.internal_transfer(&payer_id, &pool_owner_id, token_a_amount.0, None);
-			token_b.internal_transfer(&payer_id, &pool_owner_id, token_b_amount.0, None);
-			// Calc LP share of added tokens
-			let share = add_decimals(token_a_amount.0, max_decimals - token_a_meta.decimals) +
-				add_decimals(token_b_amount.0, max_decimals - token_a_meta.decimals);
-
-			// Store LP share
-			self.token_lp.internal_deposit(&payer_id, share);
-			log!("Share {} has been added to account {}", share, &payer_id);
-
-			// Update tokens data in lookup map
-			self.tokens.insert(&token_a_name, &token_a);
-			self.tokens.insert(&token_b_name, &token_b);
-		} else {
-			panic!("incorrect proportions for replenishing the liquidity pool")
-		}
-		// update ratio
-		let token_a_amount = self
-			.tokens
-			.get(&self.token_a_contract)
-			.unwrap()
-			.internal_unwrap_balance_of(&pool_owner_id);
-		let token_b_amount = self
-			.tokens
-			.get(&self.token_b_contract)
-			.unwrap()
-			.internal_unwrap_balance_of(&pool_owner_id);
-		self.token_ratio = (U128(token_a_amount), U128(token_b_amount));
+		guard: Option<TradeGuard>,
+	) -> Result<(), AmmError> {
+		let account_id = env::predecessor_account_id();
+		self.internal_add_liquidity(
+			&account_id,
+			token_a_name,
+			token_a_amount,
+			token_b_name,
+			token_b_amount,
+			guard,
+		)
 	}
 
-	// Here we are excluding all tokens of signed account from
-	// liquidity pool and return those tokens back to predecessor_account_id
-	// in the right proportion
-	pub fn exclude_tokens_from_pool(&mut self, token_a_name: AccountId, token_b_name: AccountId) {
-		assert!(self.owner_id == env::predecessor_account_id());
-		if token_a_name.eq(&token_b_name) {
-			panic!("Tokens can't be equals")
-		}
-		let mut token_a = self.tokens.get(&token_a_name).expect("Token not supported");
-		let mut token_b = self.tokens.get(&token_b_name).expect("Token not supported");
-
-		let pool_owner_id = env::current_account_id();
-		let pool_total_a = token_a.internal_unwrap_balance_of(&pool_owner_id);
-		let pool_total_b = token_b.internal_unwrap_balance_of(&pool_owner_id);
-		let predecessor_account_id = env::predecessor_account_id();
-
-		// Calc all owned user tokens in pool in proportion
-		let a = self.token_lp.total_supply * pool_total_a /
-			self.token_lp.internal_unwrap_balance_of(&predecessor_account_id);
-		let b = self.token_lp.total_supply * pool_total_b /
-			self.token_lp.internal_unwrap_balance_of(&predecessor_account_id);
-
-		// Clear user share value
-		self.token_lp.internal_withdraw(
-			&predecessor_account_id,
-			self.token_lp.internal_unwrap_balance_of(&predecessor_account_id),
-		);
-		// Transfer tokens from pool to user wallet
-		token_a.internal_transfer(&env::current_account_id(), &predecessor_account_id, a, None);
-		token_b.internal_transfer(&env::current_account_id(), &predecessor_account_id, b, None);
-		// Update tokens data in lookup map
-		self.tokens.insert(&token_a_name, &token_a);
-		self.tokens.insert(&token_b_name, &token_b);
-		// update ratio
-		let token_a_amount = self
-			.tokens
-			.get(&self.token_a_contract)
-			.unwrap()
-			.internal_unwrap_balance_of(&pool_owner_id);
-		let token_b_amount = self
-			.tokens
-			.get(&self.token_b_contract)
-			.unwrap()
-			.internal_unwrap_balance_of(&pool_owner_id);
-		self.token_ratio = (U128(token_a_amount), U128(token_b_amount));
+	// Here we are excluding all tokens of the caller from the liquidity pool
+	// and return those tokens back in the right proportion
+	#[handle_result]
+	pub fn exclude_tokens_from_pool(
+		&mut self,
+		token_a_name: AccountId,
+		token_b_name: AccountId,
+		guard: Option<TradeGuard>,
+	) -> Result<(), AmmError> {
+		let account_id = env::predecessor_account_id();
+		self.internal_remove_liquidity(&account_id, token_a_name, token_b_name, guard)
 	}
 
 	#[payable]
-	pub fn withdraw_tokens(&mut self, token_name: AccountId, amount: U128) {
+	#[handle_result]
+	pub fn withdraw_tokens(&mut self, token_name: AccountId, amount: U128) -> Result<(), AmmError> {
 		let account_id = env::predecessor_account_id();
-		if !self.tokens.contains_key(&token_name) {
-			panic!("Token not supported");
-		}
-		ext_ft::ext(token_name.clone())
-			.with_static_gas(Gas(5 * TGAS))
-			.ft_transfer(account_id, amount, None)
-			.then(
-				ext_self::ext(env::current_account_id())
-					.with_static_gas(Gas(5 * TGAS))
-					.withdraw_tokens_callback(token_name.to_string(), amount),
-			);
+		self.internal_withdraw_tokens(&account_id, token_name, amount)
 	}
 
+	// The account is passed explicitly, the signer is a relayer for delegated transactions
 	#[private]
-	pub fn withdraw_tokens_callback(&mut self, token_name: AccountId, amount: U128) {
+	pub fn withdraw_tokens_callback(
+		&mut self,
+		account_id: AccountId,
+		token_name: AccountId,
+		amount: U128,
+	) {
+		self.sub_pending_transfer(&token_name, amount.0);
 		match env::promise_result(0) {
 			PromiseResult::NotReady => unreachable!(),
-			PromiseResult::Failed => "error!".to_string(),
-			PromiseResult::Successful(_) => {
-				// Get the user who sent the tokens
-				let account_id = env::signer_account_id();
-				let mut token = self.tokens.get(&token_name).unwrap();
-
-				// Clear sent tokens value
-				token.internal_withdraw(&account_id, amount.0);
-
-				// Update token in lookup map
+			PromiseResult::Successful(_) => {},
+			PromiseResult::Failed => {
+				let mut token = self.get_token(&token_name).unwrap_or_else(|e| e.panic());
+				token.internal_deposit(&account_id, amount.0);
 				self.tokens.insert(&token_name, &token);
-				"Ok".to_string()
+				log!(
+					"Withdrawing {} of {} failed, refunded to {}",
+					amount.0,
+					token_name,
+					account_id
+				);
 			},
-		};
+		}
 	}
 
 	#[private]
+	#[handle_result]
 	pub fn on_ft_metadata(
 		&mut self,
 		contract_id: AccountId,
 		#[callback] metadata: FungibleTokenMetadata,
-	) {
+	) -> Result<(), AmmError> {
 		assert_self();
 		log!("on_ft_metadata: contract_id: {}", contract_id);
 
 		if !self.tokens.contains_key(&contract_id) {
-			panic!("Token not supported");
+			return Err(AmmError::TokenNotSupported(contract_id))
 		}
 
 		self.token_metadatas.insert(&contract_id, &metadata);
+		Ok(())
 	}
 
-	pub fn ft_balance_of(&self, token_name: AccountId, account_id: AccountId) -> U128 {
+	#[handle_result]
+	pub fn ft_balance_of(
+		&self,
+		token_name: AccountId,
+		account_id: AccountId,
+	) -> Result<U128, AmmError> {
 		if token_name == env::current_account_id() {
-			self.token_lp.ft_balance_of(account_id)
+			Ok(self.token_lp.ft_balance_of(account_id))
 		} else {
-			self.tokens
-				.get(&token_name)
-				.expect("Token not supported")
-				.ft_balance_of(account_id)
+			Ok(self.get_token(&token_name)?.ft_balance_of(account_id))
 		}
 	}
 
-	pub fn contract_info(&self) -> ContractInfo {
-		ContractInfo {
+	#[handle_result]
+	pub fn contract_info(&self) -> Result<ContractInfo, AmmError> {
+		Ok(ContractInfo {
 			owner_id: self.owner_id.clone(),
 			token_a_contract: self.token_a_contract.clone(),
 			token_b_contract: self.token_b_contract.clone(),
-			token_a_meta: self.token_metadatas.get(&self.token_a_contract).unwrap(),
-			token_b_meta: self.token_metadatas.get(&self.token_b_contract).unwrap(),
+			token_a_meta: self.get_token_metadata(&self.token_a_contract)?,
+			token_b_meta: self.get_token_metadata(&self.token_b_contract)?,
 			token_ratio: self.token_ratio,
-		}
+		})
 	}
 
 	#[payable]
 	#[allow(dead_code)]
+	#[handle_result]
 	pub fn storage_deposit(
 		&mut self,
 		token_name: AccountId,
 		account_id: AccountId,
 		registration_only: Option<bool>,
-	) {
-		if token_name == env::current_account_id() {
-			self.token_lp.storage_deposit(Some(account_id), registration_only);
+	) -> Result<(), AmmError> {
+		let registered = if token_name == env::current_account_id() {
+			let registered = self.token_lp.accounts.contains_key(&account_id);
+			self.token_lp.storage_deposit(Some(account_id.clone()), registration_only);
+			registered
 		} else {
-			let mut token = self.tokens.get(&token_name).unwrap();
-			token.storage_deposit(Some(account_id), registration_only);
+			let mut token = self.get_token(&token_name)?;
+			let registered = token.accounts.contains_key(&account_id);
+			token.storage_deposit(Some(account_id.clone()), registration_only);
 			// self.tokens.insert(&token_name, &token);
+			registered
+		};
+		if !registered {
+			self.add_token_holder(&token_name, &account_id);
 		}
+		Ok(())
 	}
 
 	#[payable]
@@ -372,7 +437,7 @@ impl Contract {
 		if token_name == env::current_account_id() {
 			self.token_lp.storage_withdraw(amount)
 		} else {
-			let mut token = self.tokens.get(&token_name).unwrap();
+			let mut token = self.get_token(&token_name).unwrap_or_else(|e| e.panic());
 			let storage_balance = token.storage_withdraw(amount);
 			// self.tokens.insert(&token_name, &token);
 			storage_balance
@@ -387,7 +452,7 @@ impl Contract {
 				return true
 			}
 		} else {
-			let mut token = self.tokens.get(&token_name).unwrap();
+			let mut token = self.get_token(&token_name).unwrap_or_else(|e| e.panic());
 			if let Some((_, _)) = token.internal_storage_unregister(force) {
 				// self.tokens.insert(&token_name, &token);
 				return true
@@ -401,7 +466,7 @@ impl Contract {
 		if token_name == env::current_account_id() {
 			self.token_lp.storage_balance_bounds()
 		} else {
-			let token = self.tokens.get(&token_name).unwrap();
+			let token = self.get_token(&token_name).unwrap_or_else(|e| e.panic());
 			token.storage_balance_bounds()
 		}
 	}
@@ -415,25 +480,417 @@ impl Contract {
 		if token_name == env::current_account_id() {
 			self.token_lp.storage_balance_of(account_id)
 		} else {
-			let token = self.tokens.get(&token_name).unwrap();
+			let token = self.get_token(&token_name).unwrap_or_else(|e| e.panic());
 			token.storage_balance_of(account_id)
 		}
 	}
 }
 
-// Use FT.ft_transfer_call to send tokens from FT to the AMM Pool
-#[near_bindgen]
-impl FungibleTokenReceiver for Contract {
-	fn ft_on_transfer(
+impl Contract {
+	// Sells sell_amount of the internal balance of account_id to the pool
+	pub(crate) fn internal_swap_tokens(
+		&mut self,
+		user_account_id: &AccountId,
+		pool_id: u64,
+		buy_token_id: AccountId,
+		sell_token_id: AccountId,
+		sell_amount: U128,
+		referral_id: Option<AccountId>,
+	) -> Result<U128, AmmError> {
+		self.assert_not_paused(Some(pool_id), PauseAction::Swaps)?;
+		if buy_token_id.eq(&sell_token_id) {
+			return Err(AmmError::TokensEqual)
+		}
+		if internal_balance_of(&self.get_token(&sell_token_id)?, user_account_id)? < sell_amount.0 {
+			return Err(AmmError::NotEnoughBalance)
+		}
+
+		let fee_bps = self.get_pool(pool_id)?.effective_fee_bps(env::block_timestamp());
+		let (buy_amount, fee) =
+			match self.internal_swap(pool_id, &sell_token_id, &buy_token_id, sell_amount.0)? {
+				Some(result) => result,
+				None => return Ok(U128(0)),
+			};
+
+		// Take sell tokens from the seller and send buy tokens to the buyer
+		let mut sell_token = self.get_token(&sell_token_id)?;
+		sell_token.internal_withdraw(user_account_id, sell_amount.0);
+		self.tokens.insert(&sell_token_id, &sell_token);
+		let mut buy_token = self.get_token(&buy_token_id)?;
+		buy_token.internal_deposit(user_account_id, buy_amount);
+		self.tokens.insert(&buy_token_id, &buy_token);
+
+		let referral_fee = match &referral_id {
+			Some(referral_id) => self.pay_referral_fee(referral_id, &sell_token_id, fee)?,
+			None => 0,
+		};
+		emit_event(
+			"swap",
+			&SwapEvent {
+				account_id: user_account_id,
+				pool_id,
+				token_in: &sell_token_id,
+				amount_in: sell_amount,
+				token_out: &buy_token_id,
+				amount_out: U128(buy_amount),
+				fee: U128(fee),
+				fee_bps,
+				referral_id: referral_id.as_ref(),
+				referral_fee: U128(referral_fee),
+			},
+		);
+
+		// The swap made buy_token more expensive, fill orders selling it
+		self.match_limit_orders(pool_id, &buy_token_id)?;
+		self.assert_invariants()?;
+
+		// Return both amount
+		Ok(U128::from(buy_amount))
+	}
+
+	// Tokens can only be added in proportion to the amount in the pool
+	pub(crate) fn internal_add_liquidity(
+		&mut self,
+		payer_id: &AccountId,
+		token_a_name: AccountId,
+		token_a_amount: U128,
+		token_b_name: AccountId,
+		token_b_amount: U128,
+		guard: Option<TradeGuard>,
+	) -> Result<(), AmmError> {
+		self.assert_owner_account(payer_id)?;
+		self.assert_not_paused(Some(DEFAULT_POOL_ID), PauseAction::Deposits)?;
+		self.assert_trade_guard(&guard)?;
+		if token_a_name.eq(&token_b_name) {
+			return Err(AmmError::TokensEqual)
+		}
+		// Whitelisted tokens outside the pool are no reserves
+		let pool = self.get_pool(DEFAULT_POOL_ID)?;
+		pool.token_index(&token_a_name)?;
+		pool.token_index(&token_b_name)?;
+
+		// Get tokens by names
+		let mut token_a = self.get_token(&token_a_name)?;
+		let mut token_b = self.get_token(&token_b_name)?;
+		let token_a_meta = self.get_token_metadata(&token_a_name)?;
+		let token_b_meta = self.get_token_metadata(&token_b_name)?;
+
+		let pool_owner_id = env::current_account_id();
+
+		// Get current state of pool
+		let pool_a_balance = internal_balance_of(&token_a, &pool_owner_id)?;
+		let pool_b_balance = internal_balance_of(&token_b, &pool_owner_id)?;
+		if internal_balance_of(&token_a, payer_id)? < token_a_amount.0 ||
+			internal_balance_of(&token_b, payer_id)? < token_b_amount.0
+		{
+			return Err(AmmError::NotEnoughBalance)
+		}
+
+		// Сonvert to the same decimal
+		let max_decimals = max(token_a_meta.decimals, token_b_meta.decimals);
+
+		// We can add tokens to the pool only by proportionally increasing them
+		if U256::from(pool_a_balance) * U256::from(token_b_amount.0) !=
+			U256::from(pool_b_balance) * U256::from(token_a_amount.0)
+		{
+			return Err(AmmError::IncorrectProportions)
+		}
+		token_a.internal_transfer(payer_id, &pool_owner_id, token_a_amount.0, None);
+		token_b.internal_transfer(payer_id, &pool_owner_id, token_b_amount.0, None);
+		// Calc LP share of added tokens
+		let share = add_decimals(token_a_amount.0, max_decimals - token_a_meta.decimals)?
+			.checked_add(add_decimals(token_b_amount.0, max_decimals - token_a_meta.decimals)?)
+			.ok_or(AmmError::MathOverflow)?;
+
+		// Store LP share
+		self.token_lp.internal_deposit(payer_id, share);
+		log!("Share {} has been added to account {}", share, payer_id);
+
+		// Update tokens data in lookup map
+		self.tokens.insert(&token_a_name, &token_a);
+		self.tokens.insert(&token_b_name, &token_b);
+		self.record_lp_deposit(payer_id, share)?;
+		self.update_ratio()?;
+		self.assert_invariants()
+	}
+
+	pub(crate) fn internal_remove_liquidity(
+		&mut self,
+		predecessor_account_id: &AccountId,
+		token_a_name: AccountId,
+		token_b_name: AccountId,
+		guard: Option<TradeGuard>,
+	) -> Result<(), AmmError> {
+		self.assert_owner_account(predecessor_account_id)?;
+		self.assert_not_paused(Some(DEFAULT_POOL_ID), PauseAction::Withdrawals)?;
+		self.assert_trade_guard(&guard)?;
+		if token_a_name.eq(&token_b_name) {
+			return Err(AmmError::TokensEqual)
+		}
+		// Whitelisted tokens outside the pool are no reserves
+		let pool = self.get_pool(DEFAULT_POOL_ID)?;
+		pool.token_index(&token_a_name)?;
+		pool.token_index(&token_b_name)?;
+		let mut token_a = self.get_token(&token_a_name)?;
+		let mut token_b = self.get_token(&token_b_name)?;
+
+		let shares = internal_balance_of(&self.token_lp, predecessor_account_id)?;
+		if shares == 0 {
+			return Err(AmmError::NoLiquidityShares)
+		}
+
+		// Calc all owned user tokens in pool in proportion
+		let (amount_a, amount_b) = self.shares_to_amounts(shares)?;
+		let (a, b) = if token_a_name == self.token_a_contract {
+			(amount_a, amount_b)
+		} else {
+			(amount_b, amount_a)
+		};
+
+		// Clear user share value
+		self.token_lp.internal_withdraw(predecessor_account_id, shares);
+		self.record_lp_withdrawal(predecessor_account_id, shares)?;
+		// Transfer tokens from pool to user wallet
+		token_a.internal_transfer(&env::current_account_id(), predecessor_account_id, a, None);
+		token_b.internal_transfer(&env::current_account_id(), predecessor_account_id, b, None);
+		// Update tokens data in lookup map
+		self.tokens.insert(&token_a_name, &token_a);
+		self.tokens.insert(&token_b_name, &token_b);
+		self.update_ratio()?;
+		self.assert_invariants()
+	}
+
+	// Sends amount of the internal balance of account_id to its wallet
+	pub(crate) fn internal_withdraw_tokens(
 		&mut self,
-		sender_id: AccountId,
+		account_id: &AccountId,
+		token_name: AccountId,
 		amount: U128,
-		#[allow(unused_variables)] msg: String,
-	) -> PromiseOrValue<U128> {
-		let token_name = &env::predecessor_account_id();
-		let mut token = self.tokens.get(token_name).expect("Token not supported");
-		token.internal_deposit(&sender_id, amount.0);
-		self.tokens.insert(token_name, &token);
-		PromiseOrValue::Value(U128::from(0_u128))
+	) -> Result<(), AmmError> {
+		self.assert_not_paused(None, PauseAction::Withdrawals)?;
+		let mut token = self.get_token(&token_name)?;
+		if internal_balance_of(&token, account_id)? < amount.0 {
+			return Err(AmmError::NotEnoughBalance)
+		}
+		// Debit first, so the balance can't be spent again before the transfer resolves. The
+		// callback restores it if the transfer fails.
+		token.internal_withdraw(account_id, amount.0);
+		self.tokens.insert(&token_name, &token);
+		self.add_pending_transfer(&token_name, amount.0);
+		self.assert_invariants()?;
+
+		ext_ft::ext(token_name.clone())
+			.with_attached_deposit(1)
+			.with_static_gas(Gas(5 * TGAS))
+			.ft_transfer(account_id.clone(), amount, None)
+			.then(
+				ext_self::ext(env::current_account_id())
+					.with_static_gas(Gas(5 * TGAS))
+					.withdraw_tokens_callback(account_id.clone(), token_name, amount),
+			);
+		Ok(())
+	}
+
+	pub(crate) fn assert_pool_exists(&self, pool_id: u64) -> Result<(), AmmError> {
+		self.get_pool(pool_id).map(|_| ())
+	}
+
+	pub(crate) fn get_token(&self, token_id: &AccountId) -> Result<FungibleToken, AmmError> {
+		self.tokens
+			.get(token_id)
+			.ok_or_else(|| AmmError::TokenNotSupported(token_id.clone()))
+	}
+
+	pub(crate) fn get_token_metadata(
+		&self,
+		token_id: &AccountId,
+	) -> Result<FungibleTokenMetadata, AmmError> {
+		self.token_metadatas
+			.get(token_id)
+			.ok_or_else(|| AmmError::TokenMetadataNotFound(token_id.clone()))
+	}
+
+	// Supported token contracts
+	pub(crate) fn token_ids(&self) -> Vec<AccountId> {
+		self.whitelisted_tokens.to_vec()
+	}
+
+	// Pool balances of (token_a, token_b)
+	pub(crate) fn pool_reserves(&self) -> Result<(u128, u128), AmmError> {
+		let pool_owner_id = env::current_account_id();
+		Ok((
+			internal_balance_of(&self.get_token(&self.token_a_contract)?, &pool_owner_id)?,
+			internal_balance_of(&self.get_token(&self.token_b_contract)?, &pool_owner_id)?,
+		))
+	}
+
+	// Amounts of (token_a, token_b) the LP shares can be exchanged for
+	pub(crate) fn shares_to_amounts(&self, shares: u128) -> Result<(u128, u128), AmmError> {
+		let total_shares = self.token_lp.total_supply;
+		if shares == 0 {
+			return Ok((0, 0))
+		}
+		let (reserve_a, reserve_b) = self.pool_reserves()?;
+		Ok((mul_div(shares, reserve_a, total_shares)?, mul_div(shares, reserve_b, total_shares)?))
+	}
+
+	// Amount of token_out and the fee for selling amount_in to the pool
+	pub(crate) fn quote_swap(
+		&self,
+		pool_id: u64,
+		token_in: &AccountId,
+		token_out: &AccountId,
+		amount_in: u128,
+	) -> Result<(u128, u128), AmmError> {
+		let pool = self.get_pool(pool_id)?;
+		pool.token_index(token_in)?;
+		pool.token_index(token_out)?;
+		let token_in_meta = self.get_token_metadata(token_in)?;
+		let token_out_meta = self.get_token_metadata(token_out)?;
+
+		// Get current state of pool
+		let pool_owner_id = env::current_account_id();
+		let reserve_in = internal_balance_of(&self.get_token(token_in)?, &pool_owner_id)?;
+		let reserve_out = internal_balance_of(&self.get_token(token_out)?, &pool_owner_id)?;
+
+		// Convert to the same decimal
+		let max_decimals = max(token_in_meta.decimals, token_out_meta.decimals);
+		let x = add_decimals(reserve_in, max_decimals - token_in_meta.decimals)?;
+		let y = add_decimals(reserve_out, max_decimals - token_out_meta.decimals)?;
+
+		// Calc buy amount, the fee stays in the pool
+		let fee = calc_fee(amount_in, pool.effective_fee_bps(env::block_timestamp()))?;
+		let amount_out = calc_dy(x, y, amount_in - fee)?;
+
+		// Restore decimal
+		Ok((remove_decimals(amount_out, max_decimals - token_out_meta.decimals)?, fee))
+	}
+
+	// Trades amount_in, which the caller already took from its owner, against the pool and
+	// returns the amount of token_out taken from the pool and the fee. Returns None without
+	// moving any tokens if the circuit breaker trips.
+	pub(crate) fn internal_swap(
+		&mut self,
+		pool_id: u64,
+		token_in: &AccountId,
+		token_out: &AccountId,
+		amount_in: u128,
+	) -> Result<Option<(u128, u128)>, AmmError> {
+		if token_in == token_out {
+			return Err(AmmError::TokensEqual)
+		}
+		let (amount_out, fee) = self.quote_swap(pool_id, token_in, token_out, amount_in)?;
+		let pool_owner_id = env::current_account_id();
+		let mut sell_token = self.get_token(token_in)?;
+		let mut buy_token = self.get_token(token_out)?;
+
+		// Check the price impact before moving any tokens
+		let reserve_in = internal_balance_of(&sell_token, &pool_owner_id)? + amount_in;
+		let reserve_out = internal_balance_of(&buy_token, &pool_owner_id)? - amount_out;
+		let (reserve_a, reserve_b) = if *token_in == self.token_a_contract {
+			(reserve_in, reserve_out)
+		} else {
+			(reserve_out, reserve_in)
+		};
+		if self.trip_circuit_breaker(pool_id, calc_price(reserve_a, reserve_b)?)? {
+			return Ok(None)
+		}
+		assert_k_not_decreased(
+			(reserve_in - amount_in, reserve_out + amount_out),
+			(reserve_in, reserve_out),
+		)?;
+
+		sell_token.internal_deposit(&pool_owner_id, amount_in);
+		buy_token.internal_withdraw(&pool_owner_id, amount_out);
+		self.tokens.insert(token_in, &sell_token);
+		self.tokens.insert(token_out, &buy_token);
+
+		let mut pool = self.get_pool(pool_id)?;
+		pool.record_swap(token_in, amount_in, token_out, amount_out, fee)?;
+		self.pools.replace(pool_id, &pool);
+		self.update_ratio()?;
+		Ok(Some((amount_out, fee)))
+	}
+
+	// Sync token_ratio with the pool balances and record the new spot price
+	fn update_ratio(&mut self) -> Result<(), AmmError> {
+		let (token_a_amount, token_b_amount) = self.pool_reserves()?;
+		self.token_ratio = (U128(token_a_amount), U128(token_b_amount));
+		if token_a_amount > 0 && token_b_amount > 0 {
+			let mut pool = self.get_pool(DEFAULT_POOL_ID)?;
+			pool.price_oracle
+				.record(env::block_timestamp(), calc_price(token_a_amount, token_b_amount)?);
+			self.pools.replace(DEFAULT_POOL_ID, &pool);
+		}
+		Ok(())
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{
+		test_utils::{accounts, VMContextBuilder},
+		testing_env, RuntimeFeesConfig, VMConfig,
+	};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	// Under a delegate action the relayer signs and the user is the predecessor
+	fn relayed(context: &mut VMContextBuilder, user: AccountId) {
+		testing_env!(context.signer_account_id(accounts(5)).predecessor_account_id(user).build());
+	}
+
+	#[test]
+	fn test_relayed_swap() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 0);
+		deposit(&mut contract, &token_b(), &accounts(2), 1_000);
+		relayed(&mut context, accounts(2));
+		let amount_out = contract.swap(token_a(), token_b(), U128(1_000), None, None).unwrap();
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), amount_out);
+		assert_eq!(contract.ft_balance_of(token_b(), accounts(2)).unwrap(), U128(0));
+	}
+
+	#[test]
+	fn test_liquidity_of_tokens_outside_the_pool() {
+		let (mut context, mut contract) = setup_pool();
+		let token_c = accounts(5);
+		contract.register_token(token_c.clone()).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(0)).build());
+		contract.on_ft_metadata(token_c.clone(), metadata("FTC", 4)).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		deposit(&mut contract, &token_c, &accounts(1), 100);
+
+		assert_eq!(
+			contract.add_tokens_to_pool(token_a(), U128(200), token_c.clone(), U128(100), None),
+			Err(AmmError::TokenNotSupported(token_c.clone()))
+		);
+		assert_eq!(
+			contract.exclude_tokens_from_pool(token_a(), token_c.clone(), None),
+			Err(AmmError::TokenNotSupported(token_c))
+		);
+		assert_eq!(contract.token_lp.total_supply, POOL_A + POOL_B);
+	}
+
+	#[test]
+	fn test_relayed_withdraw() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 1_000);
+		relayed(&mut context, accounts(2));
+		contract.withdraw_tokens(token_a(), U128(400)).unwrap();
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(600));
+
+		// The failed transfer is refunded to the user, not to the relayer
+		testing_env!(
+			context.predecessor_account_id(accounts(0)).build(),
+			VMConfig::test(),
+			RuntimeFeesConfig::test(),
+			Default::default(),
+			vec![PromiseResult::Failed],
+		);
+		contract.withdraw_tokens_callback(accounts(2), token_a(), U128(400));
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(1_000));
 	}
 }
@@ -0,0 +1,847 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	collections::{LookupMap, Vector},
+	env,
+	json_types::U128,
+	log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId,
+};
+
+use crate::*;
+
+// Most tokens a multi-asset pool can hold
+pub const MAX_POOL_TOKENS: usize = 8;
+
+// Highest amplification of a stable pool
+pub const MAX_AMP_FACTOR: u64 = 1_000_000;
+
+// Fixed point scale of the invariant growth, 1.0
+const ONE: u128 = 1_000_000_000_000_000_000;
+
+// Newton iterations of the stable invariant before giving up
+const MAX_ITERATIONS: usize = 256;
+
+#[derive(
+	BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PoolCurve {
+	// prod(x_i) = const, every token weighted equally
+	ConstantProduct,
+	// StableSwap invariant, flat around the balanced point the more the higher amp_factor is
+	Stable { amp_factor: u64 },
+}
+
+// Pool of 2 to MAX_POOL_TOKENS tokens. Unlike the token_a/token_b pool its reserves are kept in
+// `balances` and escrowed in the token ledgers, and its shares live in `MultiPools`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MultiPool {
+	pub pool_id: u64,
+	pub token_ids: Vec<AccountId>,
+	pub curve: PoolCurve,
+	// Swap fee charged from the input amount, stays in the pool
+	pub fee_bps: u32,
+	pub balances: Vec<U128>,
+	pub total_shares: U128,
+	// Decimals of each token, the invariant works on amounts scaled to the largest one
+	pub decimals: Vec<u8>,
+}
+
+// Multi-asset pools, separate from `Contract::pools`. Their ids start from 0 as well, so they
+// are paused on their own and not with `pause`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct MultiPools {
+	pub pools: Vector<MultiPool>,
+	// (pool_id, account_id):shares
+	pub shares: LookupMap<(u64, AccountId), u128>,
+	pub paused: LookupMap<u64, PauseStatus>,
+}
+
+impl MultiPools {
+	pub fn new() -> Self {
+		Self {
+			pools: Vector::new(b"mp".to_vec()),
+			shares: LookupMap::new(b"mps".to_vec()),
+			paused: LookupMap::new(b"mpp".to_vec()),
+		}
+	}
+}
+
+impl Default for MultiPools {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	// Creates an empty pool of the whitelisted tokens and returns its id
+	#[handle_result]
+	pub fn create_multi_pool(
+		&mut self,
+		token_ids: Vec<AccountId>,
+		curve: PoolCurve,
+		fee_bps: u32,
+	) -> Result<u64, AmmError> {
+		self.assert_role(Role::Admin)?;
+		if token_ids.len() < 2 || token_ids.len() > MAX_POOL_TOKENS {
+			return Err(AmmError::InvalidMultiPool)
+		}
+		if token_ids
+			.iter()
+			.enumerate()
+			.any(|(i, token_id)| token_ids[..i].contains(token_id))
+		{
+			return Err(AmmError::TokensEqual)
+		}
+		if let PoolCurve::Stable { amp_factor } = curve {
+			if amp_factor == 0 || amp_factor > MAX_AMP_FACTOR {
+				return Err(AmmError::InvalidMultiPool)
+			}
+		}
+		if fee_bps > MAX_FEE_BPS {
+			return Err(AmmError::InvalidFee(fee_bps))
+		}
+		let mut decimals = vec![];
+		for token_id in token_ids.iter() {
+			self.get_token(token_id)?;
+			decimals.push(self.get_token_metadata(token_id)?.decimals);
+		}
+
+		let pool_id = self.multi_pools.pools.len();
+		let len = token_ids.len();
+		self.multi_pools.pools.push(&MultiPool {
+			pool_id,
+			token_ids,
+			curve,
+			fee_bps,
+			balances: vec![U128(0); len],
+			total_shares: U128(0),
+			decimals,
+		});
+		log!("Multi-asset pool {} created", pool_id);
+		Ok(pool_id)
+	}
+
+	// Deposits `amounts` of every pool token, in any proportion, and returns the minted shares.
+	// The part of a deposit off the pool proportion pays a fee.
+	#[handle_result]
+	pub fn add_multi_liquidity(
+		&mut self,
+		pool_id: u64,
+		amounts: Vec<U128>,
+		min_shares: U128,
+	) -> Result<U128, AmmError> {
+		self.assert_multi_pool_not_paused(pool_id, PauseAction::Deposits)?;
+		let mut pool = self.get_multi_pool(pool_id)?;
+		let amounts = pool.check_amounts(&amounts)?;
+		let shares = if pool.total_shares.0 == 0 {
+			pool.initial_shares(&amounts)?
+		} else {
+			pool.join_shares(&amounts)?
+		};
+		if shares == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		if shares < min_shares.0 {
+			return Err(AmmError::SlippageExceeded)
+		}
+
+		let account_id = env::predecessor_account_id();
+		for (index, token_id) in pool.token_ids.iter().enumerate() {
+			if internal_balance_of(&self.get_token(token_id)?, &account_id)? < amounts[index] {
+				return Err(AmmError::NotEnoughBalance)
+			}
+		}
+		for (index, token_id) in pool.token_ids.iter().enumerate() {
+			self.internal_escrow(token_id, &account_id, amounts[index])?;
+			pool.balances[index].0 += amounts[index];
+		}
+		pool.total_shares.0 += shares;
+		self.multi_pools.pools.replace(pool_id, &pool);
+		self.add_multi_shares(pool_id, &account_id, shares);
+		log!("{} shares of multi-asset pool {} added to {}", shares, pool_id, account_id);
+		self.assert_invariants()?;
+		Ok(U128(shares))
+	}
+
+	// Burns `shares` for the proportional part of every balance, without a fee
+	#[handle_result]
+	pub fn remove_multi_liquidity(
+		&mut self,
+		pool_id: u64,
+		shares: U128,
+		min_amounts: Vec<U128>,
+	) -> Result<Vec<U128>, AmmError> {
+		self.assert_multi_pool_not_paused(pool_id, PauseAction::Withdrawals)?;
+		let mut pool = self.get_multi_pool(pool_id)?;
+		let min_amounts = pool.check_amounts(&min_amounts)?;
+		let account_id = env::predecessor_account_id();
+		if shares.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		if self.get_multi_pool_shares(pool_id, account_id.clone()).0 < shares.0 {
+			return Err(AmmError::NoLiquidityShares)
+		}
+		let mut amounts = vec![];
+		for (index, balance) in pool.balances.iter().enumerate() {
+			let amount = mul_div(balance.0, shares.0, pool.total_shares.0)?;
+			if amount < min_amounts[index] {
+				return Err(AmmError::SlippageExceeded)
+			}
+			amounts.push(amount);
+		}
+
+		self.sub_multi_shares(pool_id, &account_id, shares.0);
+		pool.total_shares.0 -= shares.0;
+		for (index, token_id) in pool.token_ids.iter().enumerate() {
+			pool.balances[index].0 -= amounts[index];
+			self.internal_release(token_id, &account_id, amounts[index])?;
+		}
+		self.multi_pools.pools.replace(pool_id, &pool);
+		self.assert_invariants()?;
+		Ok(amounts.into_iter().map(U128).collect())
+	}
+
+	// Withdraws exactly `amounts` and returns the burned shares. The part of a withdrawal off the
+	// pool proportion pays a fee, a single token exit is one non-zero amount.
+	#[handle_result]
+	pub fn remove_multi_liquidity_imbalanced(
+		&mut self,
+		pool_id: u64,
+		amounts: Vec<U128>,
+		max_shares: U128,
+	) -> Result<U128, AmmError> {
+		self.assert_multi_pool_not_paused(pool_id, PauseAction::Withdrawals)?;
+		let mut pool = self.get_multi_pool(pool_id)?;
+		let amounts = pool.check_amounts(&amounts)?;
+		let shares = pool.exit_shares(&amounts)?;
+		if shares == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		if shares > max_shares.0 {
+			return Err(AmmError::SlippageExceeded)
+		}
+		let account_id = env::predecessor_account_id();
+		if self.get_multi_pool_shares(pool_id, account_id.clone()).0 < shares {
+			return Err(AmmError::NoLiquidityShares)
+		}
+
+		self.sub_multi_shares(pool_id, &account_id, shares);
+		pool.total_shares.0 -= shares;
+		for (index, token_id) in pool.token_ids.iter().enumerate() {
+			pool.balances[index].0 -= amounts[index];
+			self.internal_release(token_id, &account_id, amounts[index])?;
+		}
+		self.multi_pools.pools.replace(pool_id, &pool);
+		self.assert_invariants()?;
+		Ok(U128(shares))
+	}
+
+	// Swaps between any two tokens of the pool using internal balances
+	#[handle_result]
+	pub fn multi_pool_swap(
+		&mut self,
+		pool_id: u64,
+		token_in: AccountId,
+		token_out: AccountId,
+		amount_in: U128,
+		min_amount_out: U128,
+	) -> Result<U128, AmmError> {
+		self.assert_multi_pool_not_paused(pool_id, PauseAction::Swaps)?;
+		let mut pool = self.get_multi_pool(pool_id)?;
+		let (index_in, index_out) = pool.swap_indexes(&token_in, &token_out)?;
+		let (amount_out, fee) = pool.quote_swap(index_in, index_out, amount_in.0)?;
+		if amount_out < min_amount_out.0 {
+			return Err(AmmError::SlippageExceeded)
+		}
+
+		let account_id = env::predecessor_account_id();
+		self.internal_escrow(&token_in, &account_id, amount_in.0)?;
+		self.internal_release(&token_out, &account_id, amount_out)?;
+		pool.balances[index_in].0 += amount_in.0;
+		pool.balances[index_out].0 -= amount_out;
+		self.multi_pools.pools.replace(pool_id, &pool);
+		log!(
+			"Swapped {} {} for {} {} in multi-asset pool {}, fee {}",
+			amount_in.0,
+			token_in,
+			amount_out,
+			token_out,
+			pool_id,
+			fee
+		);
+		self.assert_invariants()?;
+		Ok(U128(amount_out))
+	}
+
+	// Pause the given actions (all by default) of a multi-asset pool, the global pause applies
+	// to them as well
+	#[handle_result]
+	pub fn pause_multi_pool(
+		&mut self,
+		pool_id: u64,
+		actions: Option<Vec<PauseAction>>,
+	) -> Result<(), AmmError> {
+		self.assert_can_set_paused(true)?;
+		self.set_multi_pool_paused(pool_id, actions, true)
+	}
+
+	#[handle_result]
+	pub fn unpause_multi_pool(
+		&mut self,
+		pool_id: u64,
+		actions: Option<Vec<PauseAction>>,
+	) -> Result<(), AmmError> {
+		self.assert_can_set_paused(false)?;
+		self.set_multi_pool_paused(pool_id, actions, false)
+	}
+
+	// Own flags of the pool, without the global ones
+	pub fn get_multi_pool_pause_status(&self, pool_id: u64) -> PauseStatus {
+		self.multi_pools.paused.get(&pool_id).unwrap_or_default()
+	}
+
+	#[handle_result]
+	pub fn get_multi_pool(&self, pool_id: u64) -> Result<MultiPool, AmmError> {
+		self.multi_pools.pools.get(pool_id).ok_or(AmmError::MultiPoolNotFound(pool_id))
+	}
+
+	pub fn get_multi_pools(&self, from: Option<u64>, limit: Option<u64>) -> Vec<MultiPool> {
+		let from = from.unwrap_or(0);
+		let to = self
+			.multi_pools
+			.pools
+			.len()
+			.min(from.saturating_add(limit.unwrap_or(DEFAULT_PAGE_LIMIT)));
+		(from..to).filter_map(|pool_id| self.multi_pools.pools.get(pool_id)).collect()
+	}
+
+	pub fn get_multi_pool_shares(&self, pool_id: u64, account_id: AccountId) -> U128 {
+		U128(self.multi_pools.shares.get(&(pool_id, account_id)).unwrap_or(0))
+	}
+
+	// Output and fee of a swap in the current block
+	#[handle_result]
+	pub fn get_multi_pool_return(
+		&self,
+		pool_id: u64,
+		token_in: AccountId,
+		token_out: AccountId,
+		amount_in: U128,
+	) -> Result<Quote, AmmError> {
+		let pool = self.get_multi_pool(pool_id)?;
+		let (index_in, index_out) = pool.swap_indexes(&token_in, &token_out)?;
+		let (amount_out, fee) = pool.quote_swap(index_in, index_out, amount_in.0)?;
+		Ok(Quote { amount_out: U128(amount_out), fee: U128(fee), fee_bps: pool.fee_bps })
+	}
+}
+
+impl Contract {
+	fn set_multi_pool_paused(
+		&mut self,
+		pool_id: u64,
+		actions: Option<Vec<PauseAction>>,
+		paused: bool,
+	) -> Result<(), AmmError> {
+		self.get_multi_pool(pool_id)?;
+		let actions = actions.unwrap_or_else(|| PauseAction::ALL.to_vec());
+		let mut status = self.get_multi_pool_pause_status(pool_id);
+		actions.iter().for_each(|action| status.set(*action, paused));
+		self.multi_pools.paused.insert(&pool_id, &status);
+		log!(
+			"{} {:?} for multi-asset pool {}",
+			if paused { "Paused" } else { "Unpaused" },
+			actions,
+			pool_id
+		);
+		Ok(())
+	}
+
+	fn assert_multi_pool_not_paused(
+		&self,
+		pool_id: u64,
+		action: PauseAction,
+	) -> Result<(), AmmError> {
+		let paused = self.is_paused(None, action) ||
+			self.get_multi_pool_pause_status(pool_id).is_paused(action);
+		if paused {
+			return Err(AmmError::Paused(action))
+		}
+		Ok(())
+	}
+
+	fn add_multi_shares(&mut self, pool_id: u64, account_id: &AccountId, shares: u128) {
+		let key = (pool_id, account_id.clone());
+		let balance = self.multi_pools.shares.get(&key).unwrap_or(0);
+		self.multi_pools.shares.insert(&key, &(balance + shares));
+	}
+
+	fn sub_multi_shares(&mut self, pool_id: u64, account_id: &AccountId, shares: u128) {
+		let key = (pool_id, account_id.clone());
+		let balance = self.multi_pools.shares.get(&key).unwrap_or(0) - shares;
+		if balance == 0 {
+			self.multi_pools.shares.remove(&key);
+		} else {
+			self.multi_pools.shares.insert(&key, &balance);
+		}
+	}
+}
+
+impl MultiPool {
+	fn check_amounts(&self, amounts: &[U128]) -> Result<Vec<u128>, AmmError> {
+		if amounts.len() != self.token_ids.len() {
+			return Err(AmmError::InvalidMultiPool)
+		}
+		Ok(amounts.iter().map(|amount| amount.0).collect())
+	}
+
+	fn swap_indexes(
+		&self,
+		token_in: &AccountId,
+		token_out: &AccountId,
+	) -> Result<(usize, usize), AmmError> {
+		if token_in == token_out {
+			return Err(AmmError::TokensEqual)
+		}
+		let index = |token_id: &AccountId| {
+			self.token_ids
+				.iter()
+				.position(|id| id == token_id)
+				.ok_or_else(|| AmmError::TokenNotSupported(token_id.clone()))
+		};
+		Ok((index(token_in)?, index(token_out)?))
+	}
+
+	// Amount of the token at `index` in the precision of the invariant
+	fn scale(&self, index: usize, amount: u128) -> Result<u128, AmmError> {
+		add_decimals(amount, self.max_decimals() - self.decimals[index])
+	}
+
+	fn unscale(&self, index: usize, amount: u128) -> Result<u128, AmmError> {
+		remove_decimals(amount, self.max_decimals() - self.decimals[index])
+	}
+
+	fn max_decimals(&self) -> u8 {
+		self.decimals.iter().copied().max().unwrap_or(0)
+	}
+
+	fn scaled_balances(&self) -> Result<Vec<u128>, AmmError> {
+		(0..self.balances.len())
+			.map(|index| self.scale(index, self.balances[index].0))
+			.collect()
+	}
+
+	// Amount of token_out and the fee in token_in for selling amount_in to the pool
+	fn quote_swap(
+		&self,
+		index_in: usize,
+		index_out: usize,
+		amount_in: u128,
+	) -> Result<(u128, u128), AmmError> {
+		if amount_in == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		if self.total_shares.0 == 0 {
+			return Err(AmmError::EmptyPool)
+		}
+		let fee = calc_fee(amount_in, self.fee_bps)?;
+		let dx = self.scale(index_in, amount_in - fee)?;
+		let xp = self.scaled_balances()?;
+		let dy = match self.curve {
+			// Tokens not in the swap keep their balance, so only the pair's product matters
+			PoolCurve::ConstantProduct => calc_dy(xp[index_in], xp[index_out], dx)?,
+			PoolCurve::Stable { amp_factor } => {
+				let d = stable_invariant(amp_factor, &xp)?;
+				let x = xp[index_in].checked_add(dx).ok_or(AmmError::MathOverflow)?;
+				let y = stable_balance(amp_factor, index_in, index_out, x, &xp, d)?;
+				// Rounded down in favor of the pool
+				xp[index_out].saturating_sub(y).saturating_sub(1)
+			},
+		};
+		Ok((self.unscale(index_out, dy)?, fee))
+	}
+
+	// Shares of the first deposit, which needs every token
+	fn initial_shares(&self, amounts: &[u128]) -> Result<u128, AmmError> {
+		if amounts.contains(&0) {
+			return Err(AmmError::ZeroAmount)
+		}
+		let xp = (0..amounts.len())
+			.map(|index| self.scale(index, amounts[index]))
+			.collect::<Result<Vec<_>, _>>()?;
+		match self.curve {
+			PoolCurve::ConstantProduct => xp
+				.iter()
+				.try_fold(0u128, |sum, x| sum.checked_add(*x))
+				.ok_or(AmmError::MathOverflow),
+			PoolCurve::Stable { amp_factor } => stable_invariant(amp_factor, &xp),
+		}
+	}
+
+	// Shares minted for adding amounts: total_shares times the invariant growth
+	fn join_shares(&self, amounts: &[u128]) -> Result<u128, AmmError> {
+		let old = self.scaled_balances()?;
+		let mut new = old.clone();
+		for (index, amount) in amounts.iter().enumerate() {
+			new[index] = new[index]
+				.checked_add(self.scale(index, *amount)?)
+				.ok_or(AmmError::MathOverflow)?;
+		}
+		let growth = self.invariant_growth(&old, &self.charge_imbalance_fee(&old, &new)?)?;
+		mul_div(self.total_shares.0, growth.saturating_sub(ONE), ONE)
+	}
+
+	// Shares burned for withdrawing amounts, rounded up
+	fn exit_shares(&self, amounts: &[u128]) -> Result<u128, AmmError> {
+		let old = self.scaled_balances()?;
+		let mut new = old.clone();
+		for (index, amount) in amounts.iter().enumerate() {
+			if *amount >= self.balances[index].0 {
+				return Err(AmmError::NotEnoughBalance)
+			}
+			new[index] -= self.scale(index, *amount)?;
+		}
+		let growth = self.invariant_growth(&old, &self.charge_imbalance_fee(&old, &new)?)?;
+		mul_div_ceil(self.total_shares.0, ONE.saturating_sub(growth), ONE)
+	}
+
+	// Balances for the invariant after a join or exit. Each balance moved off the proportional
+	// change pays fee * n / (4 * (n - 1)) of the difference, like a swap into the proportion.
+	fn charge_imbalance_fee(&self, old: &[u128], new: &[u128]) -> Result<Vec<u128>, AmmError> {
+		let n = old.len() as u128;
+		let growth = self.invariant_growth(old, new)?;
+		let fee_bps = (self.fee_bps as u128 * n / (4 * (n - 1))) as u32;
+		let mut charged = vec![];
+		for (index, balance) in new.iter().enumerate() {
+			let ideal = mul_div(old[index], growth, ONE)?;
+			charged.push(balance - calc_fee(balance.abs_diff(ideal), fee_bps)?);
+		}
+		Ok(charged)
+	}
+
+	// Ratio of the invariant of new to the one of old, scaled by ONE and rounded down
+	fn invariant_growth(&self, old: &[u128], new: &[u128]) -> Result<u128, AmmError> {
+		match self.curve {
+			// The invariant is the geometric mean, so its growth is the mean of the growths
+			PoolCurve::ConstantProduct => {
+				let mut product = U256::from(ONE);
+				for (old, new) in old.iter().zip(new.iter()) {
+					if *old == 0 {
+						return Err(AmmError::EmptyPool)
+					}
+					product = product * U256::from(*new) / U256::from(*old);
+					if product > U256::from(u128::MAX) {
+						return Err(AmmError::MathOverflow)
+					}
+				}
+				Ok(nth_root(product.as_u128(), old.len() as u32))
+			},
+			PoolCurve::Stable { amp_factor } => {
+				let old = stable_invariant(amp_factor, old)?;
+				mul_div(stable_invariant(amp_factor, new)?, ONE, old)
+			},
+		}
+	}
+}
+
+// Largest r with (r / ONE)^n <= value / ONE, by bisection
+fn nth_root(value: u128, n: u32) -> u128 {
+	let (mut low, mut high) = (0, value.max(ONE));
+	while low < high {
+		let mid = high - (high - low) / 2;
+		if pow_exceeds(mid, n, value) {
+			high = mid - 1;
+		} else {
+			low = mid;
+		}
+	}
+	low
+}
+
+// (r / ONE)^n > value / ONE. Powers of r >= ONE only grow, so they stop early to avoid overflows.
+fn pow_exceeds(r: u128, n: u32, value: u128) -> bool {
+	let mut power = U256::from(ONE);
+	for _ in 0..n {
+		power = power * U256::from(r) / U256::from(ONE);
+		if r >= ONE && power > U256::from(value) {
+			return true
+		}
+	}
+	power > U256::from(value)
+}
+
+// StableSwap invariant D of balances xp:
+// A * n^n * sum(x_i) + D = A * D * n^n + D^(n + 1) / (n^n * prod(x_i)),
+// solved by Newton's method with Ann = A * n as in the Curve pools
+fn stable_invariant(amp_factor: u64, xp: &[u128]) -> Result<u128, AmmError> {
+	let n = U256::from(xp.len());
+	let sum = xp.iter().fold(U256::zero(), |sum, x| sum + U256::from(*x));
+	if sum.is_zero() {
+		return Ok(0)
+	}
+	let ann = U256::from(amp_factor) * n;
+	let mut d = sum;
+	for _ in 0..MAX_ITERATIONS {
+		let mut d_p = d;
+		for x in xp {
+			if *x == 0 {
+				return Err(AmmError::EmptyPool)
+			}
+			d_p = checked_mul(d_p, d)? / (U256::from(*x) * n);
+		}
+		let previous = d;
+		let numerator = checked_mul(ann * sum + checked_mul(d_p, n)?, d)?;
+		let denominator = checked_mul(ann - 1, d)?
+			.checked_add(checked_mul(n + 1, d_p)?)
+			.ok_or(AmmError::MathOverflow)?;
+		d = numerator / denominator;
+		if d.abs_diff(previous) <= U256::one() {
+			return u256_to_u128(d)
+		}
+	}
+	Err(AmmError::MathOverflow)
+}
+
+// Balance of token j keeping the invariant d when token i has balance x
+fn stable_balance(
+	amp_factor: u64,
+	i: usize,
+	j: usize,
+	x: u128,
+	xp: &[u128],
+	d: u128,
+) -> Result<u128, AmmError> {
+	let n = U256::from(xp.len());
+	let ann = U256::from(amp_factor) * n;
+	let d = U256::from(d);
+	let mut c = d;
+	let mut sum = U256::zero();
+	for (k, balance) in xp.iter().enumerate() {
+		if k == j {
+			continue
+		}
+		let balance = U256::from(if k == i { x } else { *balance });
+		if balance.is_zero() {
+			return Err(AmmError::EmptyPool)
+		}
+		sum += balance;
+		c = checked_mul(c, d)? / (balance * n);
+	}
+	c = checked_mul(c, d)? / (ann * n);
+	let b = sum + d / ann;
+	let mut y = d;
+	for _ in 0..MAX_ITERATIONS {
+		let previous = y;
+		let denominator = (U256::from(2) * y + b).checked_sub(d).ok_or(AmmError::MathOverflow)?;
+		y = checked_mul(y, y)?.checked_add(c).ok_or(AmmError::MathOverflow)? / denominator;
+		if y.abs_diff(previous) <= U256::one() {
+			return u256_to_u128(y)
+		}
+	}
+	Err(AmmError::MathOverflow)
+}
+
+fn checked_mul(a: U256, b: U256) -> Result<U256, AmmError> {
+	a.checked_mul(b).ok_or(AmmError::MathOverflow)
+}
+
+fn u256_to_u128(value: U256) -> Result<u128, AmmError> {
+	if value > U256::from(u128::MAX) {
+		return Err(AmmError::MathOverflow)
+	}
+	Ok(value.as_u128())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{
+		test_utils::{accounts, VMContextBuilder},
+		testing_env,
+	};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	// Pool of token_a, token_b and a third token with 6 decimals, funded by accounts(2)
+	fn setup_multi_pool(curve: PoolCurve) -> (VMContextBuilder, Contract, u64) {
+		let (mut context, mut contract) = setup_contract();
+		let token_c = accounts(5);
+		contract.register_token(token_c.clone()).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(0)).build());
+		contract.on_ft_metadata(token_c.clone(), metadata("FTC", 6)).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		let token_ids = vec![token_a(), token_b(), token_c];
+		let pool_id = contract.create_multi_pool(token_ids.clone(), curve, 30).unwrap();
+		for (token_id, amount) in token_ids.iter().zip([100_000, 100_000, 10_000_000]) {
+			deposit(&mut contract, token_id, &accounts(2), amount);
+		}
+
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		let amounts = vec![U128(100_000), U128(100_000), U128(10_000_000)];
+		contract.add_multi_liquidity(pool_id, amounts, U128(0)).unwrap();
+		(context, contract, pool_id)
+	}
+
+	#[test]
+	fn test_math() {
+		assert_eq!(nth_root(8 * ONE, 3), 2 * ONE);
+		assert_eq!(nth_root(ONE / 4, 2), ONE / 2);
+		// A balanced stable pool is worth the sum of its balances
+		assert_eq!(stable_invariant(100, &[1_000, 1_000, 1_000]).unwrap(), 3_000);
+		let d = stable_invariant(100, &[1_000_000, 1_200_000, 900_000]).unwrap();
+		assert!(d < 3_100_000 && d > 3_099_000);
+		let y = stable_balance(100, 0, 1, 1_100_000, &[1_000_000, 1_200_000, 900_000], d).unwrap();
+		assert!(y < 1_100_000 && y > 1_099_000);
+		assert_eq!(stable_invariant(1, &[u128::MAX; 8]), Err(AmmError::MathOverflow));
+	}
+
+	#[test]
+	fn test_create_multi_pool() {
+		let (_, mut contract) = setup_contract();
+		let curve = PoolCurve::Stable { amp_factor: 100 };
+		assert_eq!(
+			contract.create_multi_pool(vec![token_a()], curve, 30),
+			Err(AmmError::InvalidMultiPool)
+		);
+		assert_eq!(
+			contract.create_multi_pool(vec![token_a(), token_a()], curve, 30),
+			Err(AmmError::TokensEqual)
+		);
+		assert_eq!(
+			contract.create_multi_pool(vec![token_a(), accounts(5)], curve, 30),
+			Err(AmmError::TokenNotSupported(accounts(5)))
+		);
+		assert_eq!(
+			contract.create_multi_pool(
+				vec![token_a(), token_b()],
+				PoolCurve::Stable { amp_factor: 0 },
+				30
+			),
+			Err(AmmError::InvalidMultiPool)
+		);
+		assert_eq!(contract.create_multi_pool(vec![token_a(), token_b()], curve, 30), Ok(0));
+		assert_eq!(contract.unregister_token(token_a()), Err(AmmError::TokenInUse(token_a())));
+	}
+
+	#[test]
+	fn test_constant_product_pool() {
+		let (_, mut contract, pool_id) = setup_multi_pool(PoolCurve::ConstantProduct);
+		// Scaled to 6 decimals, the first deposit is worth the sum of the balances
+		let total_shares = contract.get_multi_pool(pool_id).unwrap().total_shares.0;
+		assert_eq!(total_shares, 30_000_000);
+
+		// Only the pair's balances matter in a swap
+		let quote = contract
+			.get_multi_pool_return(pool_id, token_a(), accounts(5), U128(1_000))
+			.unwrap();
+		assert_eq!(quote.amount_out.0, calc_dy(10_000_000, 10_000_000, 99_700).unwrap());
+		deposit(&mut contract, &token_a(), &accounts(2), 1_000);
+		assert_eq!(
+			contract.multi_pool_swap(pool_id, token_a(), accounts(5), U128(1_000), U128(u128::MAX)),
+			Err(AmmError::SlippageExceeded)
+		);
+		let amount_out = contract
+			.multi_pool_swap(pool_id, token_a(), accounts(5), U128(1_000), U128(0))
+			.unwrap();
+		assert_eq!(amount_out, quote.amount_out);
+		assert_eq!(contract.ft_balance_of(accounts(5), accounts(2)).unwrap(), amount_out);
+
+		// Proportional exits pay no fee
+		let amounts = contract
+			.remove_multi_liquidity(pool_id, U128(total_shares / 10), vec![U128(0); 3])
+			.unwrap();
+		assert_eq!(amounts[0], U128(10_100));
+		assert_eq!(amounts[1], U128(10_000));
+
+		// A single-sided exit of 10% of a balance burns more than 1 - 0.9^(1/3) of the shares
+		let total_shares = contract.get_multi_pool(pool_id).unwrap().total_shares.0;
+		let shares = contract
+			.remove_multi_liquidity_imbalanced(
+				pool_id,
+				vec![U128(0), U128(9_000), U128(0)],
+				U128(total_shares),
+			)
+			.unwrap()
+			.0;
+		assert!(shares > total_shares * 345 / 10_000 && shares < total_shares / 28);
+		assert_eq!(contract.ft_balance_of(token_b(), accounts(2)).unwrap(), U128(19_000));
+		contract.check_invariants().unwrap();
+	}
+
+	#[test]
+	fn test_stable_pool() {
+		let (_, mut contract, pool_id) = setup_multi_pool(PoolCurve::Stable { amp_factor: 100 });
+		let total_shares = contract.get_multi_pool(pool_id).unwrap().total_shares.0;
+		assert_eq!(total_shares, 30_000_000);
+
+		// Close to 1:1 for a swap of 10% of the balance
+		deposit(&mut contract, &token_b(), &accounts(2), 10_000);
+		let amount_out = contract
+			.multi_pool_swap(pool_id, token_b(), accounts(5), U128(10_000), U128(0))
+			.unwrap();
+		assert!(amount_out.0 > 990_000 && amount_out.0 < 997_000);
+		assert_eq!(contract.ft_balance_of(accounts(5), accounts(2)).unwrap(), amount_out);
+
+		// An imbalanced join gets fewer shares than the value it adds
+		deposit(&mut contract, &token_a(), &accounts(2), 10_000);
+		let shares = contract
+			.add_multi_liquidity(pool_id, vec![U128(10_000), U128(0), U128(0)], U128(0))
+			.unwrap()
+			.0;
+		assert!(shares > 990_000 && shares < 1_000_000);
+		assert_eq!(
+			contract.get_multi_pool_shares(pool_id, accounts(2)),
+			U128(total_shares + shares)
+		);
+
+		let pool = contract.get_multi_pool(pool_id).unwrap();
+		assert_eq!(pool.balances[0], U128(110_000));
+		assert_eq!(pool.balances[1], U128(110_000));
+		assert_eq!(contract.get_multi_pools(None, None), vec![pool]);
+		contract.check_invariants().unwrap();
+	}
+
+	#[test]
+	fn test_multi_pool_pause() {
+		let (mut context, mut contract, pool_id) = setup_multi_pool(PoolCurve::ConstantProduct);
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		contract.grant_role(Role::Guardian, accounts(3)).unwrap();
+
+		// Pausing multi-asset pool 0 leaves the default pool 0 alone
+		testing_env!(context.predecessor_account_id(accounts(3)).build());
+		contract.pause_multi_pool(pool_id, Some(vec![PauseAction::Swaps])).unwrap();
+		assert_eq!(
+			contract.unpause_multi_pool(pool_id, None),
+			Err(AmmError::MissingRole(Role::Pauser))
+		);
+		assert!(!contract.get_pause_status(Some(DEFAULT_POOL_ID)).is_paused(PauseAction::Swaps));
+		assert!(contract.get_multi_pool_pause_status(pool_id).is_paused(PauseAction::Swaps));
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		assert_eq!(
+			contract.multi_pool_swap(pool_id, token_a(), token_b(), U128(1_000), U128(0)),
+			Err(AmmError::Paused(PauseAction::Swaps))
+		);
+
+		// Positions show up next to the default pool ones
+		let account = contract.get_account(accounts(2)).unwrap();
+		assert!(account.pools.is_empty());
+		assert_eq!(account.multi_pools.len(), 1);
+		assert_eq!(
+			account.multi_pools[0].shares,
+			contract.get_multi_pool_shares(pool_id, accounts(2))
+		);
+		assert_eq!(account.multi_pools[0].amounts[2].amount, U128(10_000_000));
+
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		contract.unpause_multi_pool(pool_id, None).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		deposit(&mut contract, &token_a(), &accounts(2), 1_000);
+		contract
+			.multi_pool_swap(pool_id, token_a(), token_b(), U128(1_000), U128(0))
+			.unwrap();
+	}
+}
@@ -0,0 +1,138 @@
+use std::cmp::max;
+
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	json_types::U128,
+	serde::{Deserialize, Serialize},
+	Timestamp,
+};
+
+use crate::*;
+
+// Amount of price observations kept in the ring buffer
+pub const MAX_OBSERVATIONS: usize = 32;
+
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Observation {
+	// Block timestamp (ns) since which the price is effective
+	pub timestamp: Timestamp,
+	// Spot price of token A in token B, see `calc_price`
+	pub price: U128,
+}
+
+// Spot price history used to derive the time weighted average price (TWAP)
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct PriceOracle {
+	pub observations: Vec<Observation>,
+}
+
+impl PriceOracle {
+	// Several updates in one block keep only the last price, so a price pushed and reverted
+	// within a block never contributes to the TWAP
+	pub fn record(&mut self, timestamp: Timestamp, price: u128) {
+		match self.observations.last_mut() {
+			Some(last) if last.timestamp == timestamp => last.price = U128(price),
+			_ => {
+				if self.observations.len() == MAX_OBSERVATIONS {
+					self.observations.remove(0);
+				}
+				self.observations.push(Observation { timestamp, price: U128(price) });
+			},
+		}
+	}
+
+	pub fn latest_price(&self) -> Option<u128> {
+		self.observations.last().map(|o| o.price.0)
+	}
+
+	// Average of the recorded prices over the last `window` nanoseconds, each price weighted by
+	// the time it was effective. Returns None if no price was effective during the window yet.
+	pub fn twap(&self, now: Timestamp, window: u64) -> Option<u128> {
+		let window_start = now.saturating_sub(window);
+		let mut weighted_sum = U256::zero();
+		let mut total_time = 0_u64;
+		let mut end = now;
+		for observation in self.observations.iter().rev() {
+			let start = max(observation.timestamp, window_start);
+			if end > start {
+				weighted_sum += U256::from(observation.price.0) * U256::from(end - start);
+				total_time += end - start;
+			}
+			if observation.timestamp <= window_start {
+				break
+			}
+			end = observation.timestamp;
+		}
+		if total_time == 0 {
+			return None
+		}
+		Some((weighted_sum / U256::from(total_time)).as_u128())
+	}
+
+	// Spread between the highest and the lowest price effective during the last `window`
+	// nanoseconds in bps of the lowest one. Returns None without any observation.
+	pub fn volatility_bps(&self, now: Timestamp, window: u64) -> Option<u128> {
+		let window_start = now.saturating_sub(window);
+		let mut low = u128::MAX;
+		let mut high = 0;
+		for observation in self.observations.iter().rev() {
+			low = low.min(observation.price.0);
+			high = high.max(observation.price.0);
+			if observation.timestamp <= window_start {
+				break
+			}
+		}
+		if high == 0 {
+			return None
+		}
+		Some(price_deviation_bps(high, low).unwrap_or(u128::MAX))
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_record_same_block() {
+		let mut oracle = PriceOracle::default();
+		oracle.record(10, 100);
+		oracle.record(10, 200);
+		assert_eq!(oracle.observations.len(), 1);
+		assert_eq!(oracle.latest_price(), Some(200));
+		for i in 0..MAX_OBSERVATIONS as u64 * 2 {
+			oracle.record(20 + i, 100);
+		}
+		assert_eq!(oracle.observations.len(), MAX_OBSERVATIONS);
+	}
+
+	#[test]
+	fn test_twap() {
+		let mut oracle = PriceOracle::default();
+		assert_eq!(oracle.twap(100, 50), None);
+		oracle.record(0, 100);
+		// Price recorded in the current block has no weight yet
+		oracle.record(100, 400);
+		assert_eq!(oracle.twap(100, 50), Some(100));
+		// 100 for 50ns and 400 for 50ns
+		assert_eq!(oracle.twap(150, 100), Some(250));
+		assert_eq!(oracle.twap(150, 25), Some(400));
+	}
+
+	#[test]
+	fn test_volatility() {
+		let mut oracle = PriceOracle::default();
+		assert_eq!(oracle.volatility_bps(100, 50), None);
+		oracle.record(0, 100);
+		assert_eq!(oracle.volatility_bps(100, 50), Some(0));
+		oracle.record(60, 110);
+		oracle.record(80, 105);
+		// The price effective at the window start counts as well
+		assert_eq!(oracle.volatility_bps(100, 50), Some(1_000));
+		assert_eq!(oracle.volatility_bps(100, 30), Some(476));
+	}
+}
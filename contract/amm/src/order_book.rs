@@ -0,0 +1,353 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	collections::{LookupMap, UnorderedMap},
+	env,
+	json_types::U128,
+	log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId,
+};
+
+use crate::*;
+
+// Orders filled at the end of a single swap, bounds the gas used by matching
+pub const MAX_FILLS_PER_SWAP: usize = 5;
+
+// Open orders per (pool, sell token), the sorted book is loaded as a whole
+pub const MAX_ORDERS_PER_BOOK: usize = 200;
+
+// Smallest order in basis points of the pool reserve of the sell token, so that filling a book
+// with dust orders locks a share of the pool
+pub const MIN_ORDER_RESERVE_BPS: u128 = 1;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LimitOrder {
+	pub order_id: u64,
+	pub account_id: AccountId,
+	pub pool_id: u64,
+	pub sell_token_id: AccountId,
+	pub buy_token_id: AccountId,
+	// Escrowed sell_token amount, zero once the order is filled
+	pub amount: U128,
+	// Lowest accepted price, buy_token per sell_token scaled by PRICE_PRECISION
+	pub price: U128,
+	// Bought amount waiting to be claimed
+	pub filled: U128,
+}
+
+// Limit orders resting against the pools. An order is filled in full by swapping its escrow
+// through the pool as soon as the pool pays at least the limit price, which is checked after
+// every swap that makes the sell token more expensive.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OrderBook {
+	// order_id:order
+	pub orders: UnorderedMap<u64, LimitOrder>,
+	// (pool_id, sell_token_id):open order ids sorted by price, lowest first
+	pub books: LookupMap<(u64, AccountId), Vec<u64>>,
+	pub next_order_id: u64,
+}
+
+impl OrderBook {
+	pub fn new() -> Self {
+		Self {
+			orders: UnorderedMap::new(b"lo".to_vec()),
+			books: LookupMap::new(b"lob".to_vec()),
+			next_order_id: 0,
+		}
+	}
+}
+
+impl Default for OrderBook {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	// Escrows `amount` of sell_token_id from the internal balance of the caller and sells it for
+	// buy_token_id once the pool price reaches `price`
+	#[handle_result]
+	pub fn place_limit_order(
+		&mut self,
+		pool_id: u64,
+		sell_token_id: AccountId,
+		buy_token_id: AccountId,
+		amount: U128,
+		price: U128,
+	) -> Result<u64, AmmError> {
+		self.assert_not_paused(Some(pool_id), PauseAction::Swaps)?;
+		if sell_token_id == buy_token_id {
+			return Err(AmmError::TokensEqual)
+		}
+		let pool = self.get_pool(pool_id)?;
+		pool.token_index(&sell_token_id)?;
+		pool.token_index(&buy_token_id)?;
+		if amount.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		if price.0 == 0 {
+			return Err(AmmError::InvalidPrice)
+		}
+		let reserve = self
+			.get_token(&sell_token_id)?
+			.accounts
+			.get(&env::current_account_id())
+			.unwrap_or(0);
+		let min_amount = mul_div(reserve, MIN_ORDER_RESERVE_BPS, BPS_DENOMINATOR)?;
+		if amount.0 < min_amount {
+			return Err(AmmError::OrderTooSmall(min_amount))
+		}
+
+		let key = (pool_id, sell_token_id.clone());
+		let mut book = self.order_book.books.get(&key).unwrap_or_default();
+		if book.len() >= MAX_ORDERS_PER_BOOK {
+			return Err(AmmError::OrderBookFull)
+		}
+
+		let account_id = env::predecessor_account_id();
+		self.internal_escrow(&sell_token_id, &account_id, amount.0)?;
+
+		let order_id = self.order_book.next_order_id;
+		self.order_book.next_order_id += 1;
+		let order = LimitOrder {
+			order_id,
+			account_id,
+			pool_id,
+			sell_token_id: sell_token_id.clone(),
+			buy_token_id,
+			amount,
+			price,
+			filled: U128(0),
+		};
+		// Orders with the same price are filled in placement order
+		let position =
+			book.partition_point(|id| self.get_limit_order(*id).unwrap().price.0 <= price.0);
+		book.insert(position, order_id);
+		self.order_book.books.insert(&key, &book);
+		self.order_book.orders.insert(&order_id, &order);
+		log!("Limit order {} placed by {}", order_id, order.account_id);
+
+		// The pool may already pay the limit price
+		self.match_limit_orders(pool_id, &sell_token_id)?;
+		Ok(order_id)
+	}
+
+	// Closes the order and returns the escrow and any bought amount to the internal balances
+	#[handle_result]
+	pub fn cancel_limit_order(&mut self, order_id: u64) -> Result<(), AmmError> {
+		let order = self.get_own_limit_order(order_id)?;
+		if order.amount.0 > 0 {
+			let key = (order.pool_id, order.sell_token_id.clone());
+			let mut book = self.order_book.books.get(&key).unwrap_or_default();
+			book.retain(|id| *id != order_id);
+			self.order_book.books.insert(&key, &book);
+		}
+		self.internal_release(&order.sell_token_id, &order.account_id, order.amount.0)?;
+		self.internal_release(&order.buy_token_id, &order.account_id, order.filled.0)?;
+		self.order_book.orders.remove(&order_id);
+		log!("Limit order {} cancelled", order_id);
+		Ok(())
+	}
+
+	// Credits the bought amount of a filled order to the internal balance of its owner
+	#[handle_result]
+	pub fn claim_limit_order(&mut self, order_id: u64) -> Result<U128, AmmError> {
+		let order = self.get_own_limit_order(order_id)?;
+		if order.amount.0 > 0 {
+			return Err(AmmError::OrderNotFilled(order_id))
+		}
+		self.internal_release(&order.buy_token_id, &order.account_id, order.filled.0)?;
+		self.order_book.orders.remove(&order_id);
+		Ok(order.filled)
+	}
+
+	pub fn get_limit_order(&self, order_id: u64) -> Option<LimitOrder> {
+		self.order_book.orders.get(&order_id)
+	}
+
+	// Open orders selling sell_token_id in the pool, lowest price first
+	pub fn get_limit_orders(
+		&self,
+		pool_id: u64,
+		sell_token_id: AccountId,
+		from: Option<u64>,
+		limit: Option<u64>,
+	) -> Vec<LimitOrder> {
+		self.order_book
+			.books
+			.get(&(pool_id, sell_token_id))
+			.unwrap_or_default()
+			.into_iter()
+			.skip(from.unwrap_or(0) as usize)
+			.take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+			.filter_map(|order_id| self.get_limit_order(order_id))
+			.collect()
+	}
+}
+
+impl Contract {
+	fn get_own_limit_order(&self, order_id: u64) -> Result<LimitOrder, AmmError> {
+		let order = self.get_limit_order(order_id).ok_or(AmmError::OrderNotFound(order_id))?;
+		if order.account_id != env::predecessor_account_id() {
+			return Err(AmmError::NotOrderOwner(order_id))
+		}
+		Ok(order)
+	}
+
+	// Fills up to MAX_FILLS_PER_SWAP orders selling sell_token_id the pool can pay for, lowest
+	// price first. Stops at the first order the pool can't fill.
+	pub(crate) fn match_limit_orders(
+		&mut self,
+		pool_id: u64,
+		sell_token_id: &AccountId,
+	) -> Result<(), AmmError> {
+		let key = (pool_id, sell_token_id.clone());
+		let mut book = match self.order_book.books.get(&key) {
+			Some(book) if !book.is_empty() => book,
+			_ => return Ok(()),
+		};
+		let mut fills = 0;
+		while fills < MAX_FILLS_PER_SWAP && !book.is_empty() {
+			let mut order = self.get_limit_order(book[0]).unwrap();
+			let amount_out = match self.quote_swap(
+				pool_id,
+				&order.sell_token_id,
+				&order.buy_token_id,
+				order.amount.0,
+			) {
+				Ok((amount_out, _)) => amount_out,
+				Err(_) => break,
+			};
+			if amount_out < mul_div(order.amount.0, order.price.0, PRICE_PRECISION)? {
+				break
+			}
+			let amount_out = match self.internal_swap(
+				pool_id,
+				&order.sell_token_id,
+				&order.buy_token_id,
+				order.amount.0,
+			)? {
+				Some((amount_out, _)) => amount_out,
+				None => break,
+			};
+			log!(
+				"Limit order {} filled, {} sold for {}",
+				order.order_id,
+				order.amount.0,
+				amount_out
+			);
+			// The escrow went to the pool, the bought amount stays escrowed until claimed
+			self.sub_escrow(&order.sell_token_id, order.amount.0);
+			self.add_escrow(&order.buy_token_id, amount_out);
+			order.amount = U128(0);
+			order.filled = U128(amount_out);
+			self.order_book.orders.insert(&order.order_id, &order);
+			book.remove(0);
+			fills += 1;
+		}
+		self.order_book.books.insert(&key, &book);
+		Ok(())
+	}
+
+	// Credits an internal balance of a registered account
+	pub(crate) fn internal_deposit(
+		&mut self,
+		token_id: &AccountId,
+		account_id: &AccountId,
+		amount: u128,
+	) -> Result<(), AmmError> {
+		if amount == 0 {
+			return Ok(())
+		}
+		let mut token = self.get_token(token_id)?;
+		internal_balance_of(&token, account_id)?;
+		token.internal_deposit(account_id, amount);
+		self.tokens.insert(token_id, &token);
+		Ok(())
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_limit_order_filled_by_swap() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 1_000);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+		deposit(&mut contract, &token_b(), &accounts(5), 50_000);
+		deposit(&mut contract, &token_a(), &accounts(5), 0);
+
+		// Spot price is 0.5 token_b per token_a, ask for 0.6
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		let price = U128(PRICE_PRECISION * 6 / 10);
+		assert_eq!(
+			contract.place_limit_order(DEFAULT_POOL_ID, token_a(), token_b(), U128(2_000), price),
+			Err(AmmError::NotEnoughBalance)
+		);
+		assert_eq!(
+			contract.place_limit_order(DEFAULT_POOL_ID, token_a(), token_b(), U128(39), price),
+			Err(AmmError::OrderTooSmall(POOL_A / 10_000))
+		);
+		let order_id = contract
+			.place_limit_order(DEFAULT_POOL_ID, token_a(), token_b(), U128(1_000), price)
+			.unwrap();
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(0));
+		assert_eq!(contract.get_limit_orders(DEFAULT_POOL_ID, token_a(), None, None).len(), 1);
+		assert_eq!(contract.claim_limit_order(order_id), Err(AmmError::OrderNotFilled(order_id)));
+
+		// A small swap doesn't reach the price
+		testing_env!(context.predecessor_account_id(accounts(5)).build());
+		contract.swap(token_a(), token_b(), U128(1_000), None, None).unwrap();
+		assert_eq!(contract.get_limit_order(order_id).unwrap().filled, U128(0));
+
+		// A big one does and the order is sold right after it
+		contract.swap(token_a(), token_b(), U128(49_000), None, None).unwrap();
+		let order = contract.get_limit_order(order_id).unwrap();
+		assert_eq!(order.amount, U128(0));
+		assert!(order.filled.0 >= 600);
+		assert!(contract.get_limit_orders(DEFAULT_POOL_ID, token_a(), None, None).is_empty());
+		assert_eq!(contract.claim_limit_order(order_id), Err(AmmError::NotOrderOwner(order_id)));
+
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		assert_eq!(contract.claim_limit_order(order_id).unwrap(), order.filled);
+		assert_eq!(contract.ft_balance_of(token_b(), accounts(2)).unwrap(), order.filled);
+		assert_eq!(contract.get_limit_order(order_id), None);
+	}
+
+	#[test]
+	fn test_cancel_limit_order() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_b(), &accounts(2), 3_000);
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+
+		// Orders are sorted by price whatever the placement order
+		let prices = [5, 3, 4].map(|price| U128(PRICE_PRECISION * price));
+		let order_ids: Vec<u64> = prices
+			.iter()
+			.map(|price| {
+				contract
+					.place_limit_order(DEFAULT_POOL_ID, token_b(), token_a(), U128(1_000), *price)
+					.unwrap()
+			})
+			.collect();
+		let book = contract.get_limit_orders(DEFAULT_POOL_ID, token_b(), None, None);
+		let ids: Vec<u64> = book.iter().map(|order| order.order_id).collect();
+		assert_eq!(ids, vec![order_ids[1], order_ids[2], order_ids[0]]);
+
+		contract.cancel_limit_order(order_ids[2]).unwrap();
+		assert_eq!(contract.ft_balance_of(token_b(), accounts(2)).unwrap(), U128(1_000));
+		assert_eq!(contract.get_limit_orders(DEFAULT_POOL_ID, token_b(), None, None).len(), 2);
+		assert_eq!(
+			contract.cancel_limit_order(order_ids[2]),
+			Err(AmmError::OrderNotFound(order_ids[2]))
+		);
+	}
+}
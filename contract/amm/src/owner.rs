@@ -0,0 +1,187 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	env, log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId,
+};
+
+use crate::*;
+
+// Operational roles which can be granted to accounts besides the owner
+#[derive(
+	BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Role {
+	// Can grant and revoke every role except Admin
+	Admin,
+	// Can change fee settings
+	FeeManager,
+	// Can pause and unpause the contract
+	Pauser,
+	// Can only pause the contract, e.g. a monitoring bot key
+	Guardian,
+}
+
+#[near_bindgen]
+impl Contract {
+	// First step of the ownership transfer, the new owner has to call `accept_owner`
+	#[handle_result]
+	pub fn propose_owner(&mut self, new_owner_id: AccountId) -> Result<(), AmmError> {
+		self.assert_owner()?;
+		log!("Ownership proposed to {}", new_owner_id);
+		self.pending_owner_id = Some(new_owner_id);
+		Ok(())
+	}
+
+	// Second step of the ownership transfer, called by the proposed owner
+	#[handle_result]
+	pub fn accept_owner(&mut self) -> Result<(), AmmError> {
+		let account_id = env::predecessor_account_id();
+		let pending_owner_id = self.pending_owner_id.take().ok_or(AmmError::NoPendingOwner)?;
+		if pending_owner_id != account_id {
+			return Err(AmmError::NotProposedOwner)
+		}
+		log!("Ownership transferred from {} to {}", self.owner_id, account_id);
+		self.owner_id = account_id;
+		Ok(())
+	}
+
+	#[handle_result]
+	pub fn grant_role(&mut self, role: Role, account_id: AccountId) -> Result<(), AmmError> {
+		self.assert_role_manager(role)?;
+		let mut roles = self.roles.get(&account_id).unwrap_or_default();
+		if !roles.contains(&role) {
+			roles.push(role);
+			self.roles.insert(&account_id, &roles);
+			log!("Role {:?} granted to {}", role, account_id);
+		}
+		Ok(())
+	}
+
+	#[handle_result]
+	pub fn revoke_role(&mut self, role: Role, account_id: AccountId) -> Result<(), AmmError> {
+		self.assert_role_manager(role)?;
+		if let Some(mut roles) = self.roles.get(&account_id) {
+			roles.retain(|r| *r != role);
+			if roles.is_empty() {
+				self.roles.remove(&account_id);
+			} else {
+				self.roles.insert(&account_id, &roles);
+			}
+			log!("Role {:?} revoked from {}", role, account_id);
+		}
+		Ok(())
+	}
+
+	pub fn get_owner(&self) -> AccountId {
+		self.owner_id.clone()
+	}
+
+	pub fn get_pending_owner(&self) -> Option<AccountId> {
+		self.pending_owner_id.clone()
+	}
+
+	pub fn get_roles(&self, account_id: AccountId) -> Vec<Role> {
+		self.roles.get(&account_id).unwrap_or_default()
+	}
+
+	pub fn has_role(&self, role: Role, account_id: AccountId) -> bool {
+		self.roles.get(&account_id).is_some_and(|roles| roles.contains(&role))
+	}
+
+	pub fn get_role_members(&self, role: Role) -> Vec<AccountId> {
+		self.roles
+			.iter()
+			.filter(|(_, roles)| roles.contains(&role))
+			.map(|(account_id, _)| account_id)
+			.collect()
+	}
+}
+
+impl Contract {
+	pub(crate) fn assert_owner(&self) -> Result<(), AmmError> {
+		self.assert_owner_account(&env::predecessor_account_id())
+	}
+
+	pub(crate) fn assert_owner_account(&self, account_id: &AccountId) -> Result<(), AmmError> {
+		if self.owner_id != *account_id {
+			return Err(AmmError::NotOwner)
+		}
+		Ok(())
+	}
+
+	// The owner implicitly has every role
+	pub(crate) fn assert_role(&self, role: Role) -> Result<(), AmmError> {
+		let account_id = env::predecessor_account_id();
+		if account_id != self.owner_id && !self.has_role(role, account_id) {
+			return Err(AmmError::MissingRole(role))
+		}
+		Ok(())
+	}
+
+	// Admins are managed by the owner, all other roles by the owner or an admin
+	fn assert_role_manager(&self, role: Role) -> Result<(), AmmError> {
+		if role == Role::Admin {
+			self.assert_owner()
+		} else {
+			self.assert_role(Role::Admin)
+		}
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_transfer_ownership() {
+		let (mut context, mut contract) = setup_new_contract();
+		contract.propose_owner(accounts(2)).unwrap();
+		assert_eq!(contract.get_pending_owner(), Some(accounts(2)));
+		assert_eq!(contract.get_owner(), accounts(1));
+
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		contract.accept_owner().unwrap();
+		assert_eq!(contract.get_owner(), accounts(2));
+		assert_eq!(contract.get_pending_owner(), None);
+	}
+
+	#[test]
+	fn test_accept_owner_by_other_account() {
+		let (mut context, mut contract) = setup_new_contract();
+		contract.propose_owner(accounts(2)).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(3)).build());
+		assert_eq!(contract.accept_owner(), Err(AmmError::NotProposedOwner));
+	}
+
+	#[test]
+	fn test_grant_and_revoke_roles() {
+		let (mut context, mut contract) = setup_new_contract();
+		contract.grant_role(Role::Admin, accounts(2)).unwrap();
+
+		// Admin manages operational roles
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		contract.grant_role(Role::Pauser, accounts(3)).unwrap();
+		contract.grant_role(Role::Guardian, accounts(3)).unwrap();
+		assert!(contract.has_role(Role::Pauser, accounts(3)));
+		assert_eq!(contract.get_roles(accounts(3)), vec![Role::Pauser, Role::Guardian]);
+		assert_eq!(contract.get_role_members(Role::Guardian), vec![accounts(3)]);
+
+		contract.revoke_role(Role::Pauser, accounts(3)).unwrap();
+		assert!(!contract.has_role(Role::Pauser, accounts(3)));
+		assert_eq!(contract.get_roles(accounts(3)), vec![Role::Guardian]);
+	}
+
+	#[test]
+	fn test_admin_cannot_grant_admin() {
+		let (mut context, mut contract) = setup_new_contract();
+		contract.grant_role(Role::Admin, accounts(2)).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		assert_eq!(contract.grant_role(Role::Admin, accounts(3)), Err(AmmError::NotOwner));
+	}
+}
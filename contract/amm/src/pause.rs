@@ -0,0 +1,269 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	env, log, near_bindgen,
+	serde::{Deserialize, Serialize},
+};
+
+use crate::*;
+
+#[derive(
+	BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PauseAction {
+	Swaps,
+	Deposits,
+	Withdrawals,
+}
+
+impl PauseAction {
+	pub const ALL: [PauseAction; 3] =
+		[PauseAction::Swaps, PauseAction::Deposits, PauseAction::Withdrawals];
+}
+
+#[derive(
+	BorshDeserialize,
+	BorshSerialize,
+	Serialize,
+	Deserialize,
+	Clone,
+	Copy,
+	Debug,
+	Default,
+	PartialEq,
+	Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseStatus {
+	pub swaps: bool,
+	pub deposits: bool,
+	pub withdrawals: bool,
+}
+
+impl PauseStatus {
+	pub fn is_paused(&self, action: PauseAction) -> bool {
+		match action {
+			PauseAction::Swaps => self.swaps,
+			PauseAction::Deposits => self.deposits,
+			PauseAction::Withdrawals => self.withdrawals,
+		}
+	}
+
+	pub fn set(&mut self, action: PauseAction, paused: bool) {
+		match action {
+			PauseAction::Swaps => self.swaps = paused,
+			PauseAction::Deposits => self.deposits = paused,
+			PauseAction::Withdrawals => self.withdrawals = paused,
+		}
+	}
+}
+
+// Swaps are halted when a single trade moves the spot price further than
+// `max_price_deviation_bps` away from the TWAP over `twap_window_sec`
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CircuitBreaker {
+	pub max_price_deviation_bps: u32,
+	pub twap_window_sec: u32,
+}
+
+#[near_bindgen]
+impl Contract {
+	// Pause the given actions (all by default) globally or for a single pool.
+	// Guardians can only pause, unpausing requires the Pauser role.
+	#[handle_result]
+	pub fn pause(
+		&mut self,
+		pool_id: Option<u64>,
+		actions: Option<Vec<PauseAction>>,
+	) -> Result<(), AmmError> {
+		self.assert_can_set_paused(true)?;
+		self.set_paused(pool_id, actions, true)
+	}
+
+	#[handle_result]
+	pub fn unpause(
+		&mut self,
+		pool_id: Option<u64>,
+		actions: Option<Vec<PauseAction>>,
+	) -> Result<(), AmmError> {
+		self.assert_can_set_paused(false)?;
+		self.set_paused(pool_id, actions, false)
+	}
+
+	// Pause status of a pool (own flags only) or the global one
+	pub fn get_pause_status(&self, pool_id: Option<u64>) -> PauseStatus {
+		match pool_id {
+			Some(pool_id) => self.paused_pools.get(&pool_id).unwrap_or_default(),
+			None => self.paused,
+		}
+	}
+
+	// Disable the circuit breaker by passing None
+	#[handle_result]
+	pub fn set_circuit_breaker(
+		&mut self,
+		circuit_breaker: Option<CircuitBreaker>,
+	) -> Result<(), AmmError> {
+		self.assert_role(Role::Admin)?;
+		if let Some(config) = circuit_breaker {
+			if config.max_price_deviation_bps == 0 || config.twap_window_sec == 0 {
+				return Err(AmmError::InvalidCircuitBreaker)
+			}
+		}
+		self.circuit_breaker = circuit_breaker;
+		Ok(())
+	}
+
+	pub fn get_circuit_breaker(&self) -> Option<CircuitBreaker> {
+		self.circuit_breaker
+	}
+}
+
+impl Contract {
+	// Guardians can only pause, unpausing requires the Pauser role
+	pub(crate) fn assert_can_set_paused(&self, paused: bool) -> Result<(), AmmError> {
+		if paused && self.has_role(Role::Guardian, env::predecessor_account_id()) {
+			return Ok(())
+		}
+		self.assert_role(Role::Pauser)
+	}
+
+	fn set_paused(
+		&mut self,
+		pool_id: Option<u64>,
+		actions: Option<Vec<PauseAction>>,
+		paused: bool,
+	) -> Result<(), AmmError> {
+		let actions = actions.unwrap_or_else(|| PauseAction::ALL.to_vec());
+		match pool_id {
+			Some(pool_id) => {
+				self.assert_pool_exists(pool_id)?;
+				let mut status = self.paused_pools.get(&pool_id).unwrap_or_default();
+				actions.iter().for_each(|action| status.set(*action, paused));
+				self.paused_pools.insert(&pool_id, &status);
+			},
+			None => actions.iter().for_each(|action| self.paused.set(*action, paused)),
+		}
+		log!(
+			"{} {:?} for {}",
+			if paused { "Paused" } else { "Unpaused" },
+			actions,
+			pool_id.map_or("all pools".to_string(), |id| format!("pool {}", id))
+		);
+		Ok(())
+	}
+
+	pub(crate) fn is_paused(&self, pool_id: Option<u64>, action: PauseAction) -> bool {
+		self.paused.is_paused(action) ||
+			pool_id.is_some_and(|pool_id| self.get_pause_status(Some(pool_id)).is_paused(action))
+	}
+
+	pub(crate) fn assert_not_paused(
+		&self,
+		pool_id: Option<u64>,
+		action: PauseAction,
+	) -> Result<(), AmmError> {
+		if self.is_paused(pool_id, action) {
+			return Err(AmmError::Paused(action))
+		}
+		Ok(())
+	}
+
+	// Returns true and pauses swaps of the pool if the trade moves the price too far from the
+	// TWAP. The price history of the current block is ignored, so it can't be manipulated.
+	pub(crate) fn trip_circuit_breaker(
+		&mut self,
+		pool_id: u64,
+		price_after: u128,
+	) -> Result<bool, AmmError> {
+		let config = match self.circuit_breaker {
+			Some(config) => config,
+			None => return Ok(false),
+		};
+		let window = config.twap_window_sec as u64 * NANOS_PER_SECOND;
+		let twap = match self.get_pool(pool_id)?.price_oracle.twap(env::block_timestamp(), window) {
+			Some(twap) if twap > 0 => twap,
+			_ => return Ok(false),
+		};
+		let deviation = price_deviation_bps(price_after, twap)?;
+		if deviation <= config.max_price_deviation_bps as u128 {
+			return Ok(false)
+		}
+		let mut status = self.paused_pools.get(&pool_id).unwrap_or_default();
+		status.set(PauseAction::Swaps, true);
+		self.paused_pools.insert(&pool_id, &status);
+		log!(
+			"Circuit breaker tripped for pool {}: price {} deviates {} bps from TWAP {}",
+			pool_id,
+			price_after,
+			deviation,
+			twap
+		);
+		Ok(true)
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_pause_scopes() {
+		let (_, mut contract) = setup_new_contract();
+		contract.pause(Some(DEFAULT_POOL_ID), Some(vec![PauseAction::Swaps])).unwrap();
+		assert!(contract.is_paused(Some(DEFAULT_POOL_ID), PauseAction::Swaps));
+		assert!(!contract.is_paused(Some(DEFAULT_POOL_ID), PauseAction::Withdrawals));
+		assert!(!contract.is_paused(None, PauseAction::Swaps));
+		assert_eq!(
+			contract.assert_not_paused(Some(DEFAULT_POOL_ID), PauseAction::Swaps),
+			Err(AmmError::Paused(PauseAction::Swaps))
+		);
+
+		contract.pause(None, None).unwrap();
+		assert!(contract.is_paused(None, PauseAction::Withdrawals));
+		contract.unpause(None, Some(vec![PauseAction::Withdrawals])).unwrap();
+		assert_eq!(
+			contract.get_pause_status(None),
+			PauseStatus { swaps: true, deposits: true, withdrawals: false }
+		);
+	}
+
+	#[test]
+	fn test_guardian_cannot_unpause() {
+		let (mut context, mut contract) = setup_new_contract();
+		contract.grant_role(Role::Guardian, accounts(2)).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		contract.pause(None, None).unwrap();
+		assert!(contract.is_paused(None, PauseAction::Swaps));
+		assert_eq!(contract.unpause(None, None), Err(AmmError::MissingRole(Role::Pauser)));
+	}
+
+	#[test]
+	fn test_circuit_breaker() {
+		let (mut context, mut contract) = setup_new_contract();
+		contract
+			.set_circuit_breaker(Some(CircuitBreaker {
+				max_price_deviation_bps: 1_000,
+				twap_window_sec: 60,
+			}))
+			.unwrap();
+		let mut pool = contract.get_pool(DEFAULT_POOL_ID).unwrap();
+		pool.price_oracle.record(0, PRICE_PRECISION);
+		contract.pools.replace(DEFAULT_POOL_ID, &pool);
+		testing_env!(context.block_timestamp(60 * NANOS_PER_SECOND).build());
+
+		assert!(!contract
+			.trip_circuit_breaker(DEFAULT_POOL_ID, PRICE_PRECISION * 11 / 10)
+			.unwrap());
+		assert!(!contract.is_paused(Some(DEFAULT_POOL_ID), PauseAction::Swaps));
+		assert!(contract
+			.trip_circuit_breaker(DEFAULT_POOL_ID, PRICE_PRECISION * 12 / 10)
+			.unwrap());
+		assert!(contract.is_paused(Some(DEFAULT_POOL_ID), PauseAction::Swaps));
+	}
+}
@@ -0,0 +1,309 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	env,
+	json_types::{U128, U64},
+	near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId, Timestamp,
+};
+
+use crate::*;
+
+// Highest swap fee the fee manager can set, 10%
+pub const MAX_FEE_BPS: u32 = 1_000;
+
+// Amount of pools returned by `get_pools` without a limit
+pub const DEFAULT_PAGE_LIMIT: u64 = 100;
+
+// Fee following the recent price movement, `min_fee_bps` plus `volatility_multiplier_bps` of the
+// price spread over the window, capped at `max_fee_bps`
+#[derive(
+	BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DynamicFee {
+	pub min_fee_bps: u32,
+	pub max_fee_bps: u32,
+	pub volatility_multiplier_bps: u32,
+	pub window_sec: u32,
+}
+
+// Pool settings and trading statistics. Reserves of the token_a/token_b pool are the AMM
+// account balances in `tokens`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Pool {
+	pub token_ids: Vec<AccountId>,
+	// Swap fee charged from the input amount, stays in the pool
+	pub fee_bps: u32,
+	// Replaces fee_bps if set
+	pub dynamic_fee: Option<DynamicFee>,
+	// Cumulative amounts swapped into the pool per token
+	pub volumes_in: Vec<u128>,
+	// Cumulative amounts swapped out of the pool per token
+	pub volumes_out: Vec<u128>,
+	// Cumulative fees charged per token
+	pub fees: Vec<u128>,
+	pub swap_count: u64,
+	pub last_trade_timestamp: Timestamp,
+	pub price_oracle: PriceOracle,
+}
+
+impl Pool {
+	pub fn new(token_ids: Vec<AccountId>) -> Self {
+		let len = token_ids.len();
+		Self {
+			token_ids,
+			fee_bps: 0,
+			dynamic_fee: None,
+			volumes_in: vec![0; len],
+			volumes_out: vec![0; len],
+			fees: vec![0; len],
+			swap_count: 0,
+			last_trade_timestamp: 0,
+			price_oracle: PriceOracle::default(),
+		}
+	}
+
+	pub fn token_index(&self, token_id: &AccountId) -> Result<usize, AmmError> {
+		self.token_ids
+			.iter()
+			.position(|id| id == token_id)
+			.ok_or_else(|| AmmError::TokenNotSupported(token_id.clone()))
+	}
+
+	// Fee charged by swaps at `now`
+	pub fn effective_fee_bps(&self, now: Timestamp) -> u32 {
+		let config = match self.dynamic_fee {
+			Some(config) => config,
+			None => return self.fee_bps,
+		};
+		let window = config.window_sec as u64 * NANOS_PER_SECOND;
+		let volatility_bps = self.price_oracle.volatility_bps(now, window).unwrap_or(0);
+		let fee_bps = volatility_bps.saturating_mul(config.volatility_multiplier_bps as u128) /
+			BPS_DENOMINATOR;
+		(config.min_fee_bps as u128)
+			.saturating_add(fee_bps)
+			.min(config.max_fee_bps as u128) as u32
+	}
+
+	pub fn record_swap(
+		&mut self,
+		token_in: &AccountId,
+		amount_in: u128,
+		token_out: &AccountId,
+		amount_out: u128,
+		fee: u128,
+	) -> Result<(), AmmError> {
+		let index_in = self.token_index(token_in)?;
+		let index_out = self.token_index(token_out)?;
+		self.volumes_in[index_in] = self.volumes_in[index_in].saturating_add(amount_in);
+		self.volumes_out[index_out] = self.volumes_out[index_out].saturating_add(amount_out);
+		self.fees[index_in] = self.fees[index_in].saturating_add(fee);
+		self.swap_count += 1;
+		self.last_trade_timestamp = env::block_timestamp();
+		Ok(())
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolInfo {
+	pub pool_id: u64,
+	pub token_ids: Vec<AccountId>,
+	pub amounts: Vec<U128>,
+	pub total_shares: U128,
+	// Fee charged by a swap right now
+	pub fee_bps: u32,
+	pub dynamic_fee: Option<DynamicFee>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Quote {
+	pub amount_out: U128,
+	// Fee in token_in
+	pub fee: U128,
+	pub fee_bps: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolStats {
+	pub pool_id: u64,
+	pub token_ids: Vec<AccountId>,
+	pub volumes_in: Vec<U128>,
+	pub volumes_out: Vec<U128>,
+	pub fees: Vec<U128>,
+	pub swap_count: u64,
+	pub last_trade_timestamp: U64,
+}
+
+#[near_bindgen]
+impl Contract {
+	#[handle_result]
+	pub fn get_pools(
+		&self,
+		from: Option<u64>,
+		limit: Option<u64>,
+	) -> Result<Vec<PoolInfo>, AmmError> {
+		let from = from.unwrap_or(0);
+		let to = self.pools.len().min(from.saturating_add(limit.unwrap_or(DEFAULT_PAGE_LIMIT)));
+		(from..to).map(|pool_id| self.get_pool_info(pool_id)).collect()
+	}
+
+	#[handle_result]
+	pub fn get_pool_info(&self, pool_id: u64) -> Result<PoolInfo, AmmError> {
+		let pool = self.get_pool(pool_id)?;
+		let (reserve_a, reserve_b) = self.pool_reserves()?;
+		let fee_bps = pool.effective_fee_bps(env::block_timestamp());
+		Ok(PoolInfo {
+			pool_id,
+			token_ids: pool.token_ids,
+			amounts: vec![U128(reserve_a), U128(reserve_b)],
+			total_shares: U128(self.token_lp.total_supply),
+			fee_bps,
+			dynamic_fee: pool.dynamic_fee,
+		})
+	}
+
+	#[handle_result]
+	pub fn get_pool_stats(&self, pool_id: u64) -> Result<PoolStats, AmmError> {
+		let pool = self.get_pool(pool_id)?;
+		let to_u128 = |values: Vec<u128>| values.into_iter().map(U128).collect();
+		Ok(PoolStats {
+			pool_id,
+			token_ids: pool.token_ids,
+			volumes_in: to_u128(pool.volumes_in),
+			volumes_out: to_u128(pool.volumes_out),
+			fees: to_u128(pool.fees),
+			swap_count: pool.swap_count,
+			last_trade_timestamp: U64(pool.last_trade_timestamp),
+		})
+	}
+
+	#[handle_result]
+	pub fn set_pool_fee(&mut self, pool_id: u64, fee_bps: u32) -> Result<(), AmmError> {
+		self.assert_role(Role::FeeManager)?;
+		if fee_bps > MAX_FEE_BPS {
+			return Err(AmmError::InvalidFee(fee_bps))
+		}
+		let mut pool = self.get_pool(pool_id)?;
+		pool.fee_bps = fee_bps;
+		self.pools.replace(pool_id, &pool);
+		Ok(())
+	}
+
+	// Switches the pool to the dynamic fee, or back to fee_bps with None
+	#[handle_result]
+	pub fn set_pool_dynamic_fee(
+		&mut self,
+		pool_id: u64,
+		dynamic_fee: Option<DynamicFee>,
+	) -> Result<(), AmmError> {
+		self.assert_role(Role::FeeManager)?;
+		if let Some(config) = dynamic_fee {
+			if config.max_fee_bps > MAX_FEE_BPS ||
+				config.min_fee_bps > config.max_fee_bps ||
+				config.window_sec == 0
+			{
+				return Err(AmmError::InvalidDynamicFee)
+			}
+		}
+		let mut pool = self.get_pool(pool_id)?;
+		pool.dynamic_fee = dynamic_fee;
+		self.pools.replace(pool_id, &pool);
+		Ok(())
+	}
+
+	// Output and fee of a swap in the current block
+	#[handle_result]
+	pub fn get_return(
+		&self,
+		pool_id: u64,
+		token_in: AccountId,
+		token_out: AccountId,
+		amount_in: U128,
+	) -> Result<Quote, AmmError> {
+		let fee_bps = self.get_pool(pool_id)?.effective_fee_bps(env::block_timestamp());
+		let (amount_out, fee) = self.quote_swap(pool_id, &token_in, &token_out, amount_in.0)?;
+		Ok(Quote { amount_out: U128(amount_out), fee: U128(fee), fee_bps })
+	}
+}
+
+impl Contract {
+	pub(crate) fn get_pool(&self, pool_id: u64) -> Result<Pool, AmmError> {
+		self.pools.get(pool_id).ok_or(AmmError::PoolNotFound(pool_id))
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_pool_stats() {
+		let (mut context, mut contract) = setup_pool();
+		contract.grant_role(Role::FeeManager, accounts(2)).unwrap();
+		deposit(&mut contract, &token_a(), &accounts(2), 10_000);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+
+		testing_env!(context.predecessor_account_id(accounts(2)).block_timestamp(42).build());
+		assert_eq!(contract.set_pool_fee(DEFAULT_POOL_ID, 5_000), Err(AmmError::InvalidFee(5_000)));
+		contract.set_pool_fee(DEFAULT_POOL_ID, 100).unwrap();
+		let amount_out = contract.swap(token_b(), token_a(), U128(10_000), None, None).unwrap();
+		// 1% fee is charged from the input
+		assert_eq!(amount_out.0, calc_dy(POOL_A, POOL_B, 9_900).unwrap());
+
+		let stats = contract.get_pool_stats(DEFAULT_POOL_ID).unwrap();
+		assert_eq!(stats.volumes_in, vec![U128(10_000), U128(0)]);
+		assert_eq!(stats.volumes_out, vec![U128(0), amount_out]);
+		assert_eq!(stats.fees, vec![U128(100), U128(0)]);
+		assert_eq!(stats.swap_count, 1);
+		assert_eq!(stats.last_trade_timestamp, U64(42));
+
+		let pools = contract.get_pools(None, None).unwrap();
+		assert_eq!(pools.len(), 1);
+		assert_eq!(pools[0].amounts, vec![U128(POOL_A + 10_000), U128(POOL_B - amount_out.0)]);
+		assert_eq!(pools[0].fee_bps, 100);
+		assert!(contract.get_pools(Some(1), Some(10)).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_dynamic_fee() {
+		let (mut context, mut contract) = setup_pool();
+		let mut dynamic_fee = DynamicFee {
+			min_fee_bps: 10,
+			max_fee_bps: 5_000,
+			volatility_multiplier_bps: 1_000,
+			window_sec: 600,
+		};
+		assert_eq!(
+			contract.set_pool_dynamic_fee(DEFAULT_POOL_ID, Some(dynamic_fee)),
+			Err(AmmError::InvalidDynamicFee)
+		);
+		dynamic_fee.max_fee_bps = 100;
+		contract.set_pool_dynamic_fee(DEFAULT_POOL_ID, Some(dynamic_fee)).unwrap();
+
+		// Calm market pays the minimum
+		let quote = contract
+			.get_return(DEFAULT_POOL_ID, token_b(), token_a(), U128(100_000))
+			.unwrap();
+		assert_eq!((quote.fee_bps, quote.fee), (10, U128(100)));
+
+		deposit(&mut contract, &token_b(), &accounts(2), 100_000);
+		deposit(&mut contract, &token_a(), &accounts(2), 0);
+		testing_env!(context.predecessor_account_id(accounts(2)).block_timestamp(1).build());
+		let amount_out = contract.swap(token_a(), token_b(), U128(100_000), None, None).unwrap();
+		assert_eq!(amount_out, quote.amount_out);
+
+		// The price more than doubled, the fee is capped
+		let quote =
+			contract.get_return(DEFAULT_POOL_ID, token_b(), token_a(), U128(1_000)).unwrap();
+		assert_eq!(quote.fee_bps, 100);
+		assert_eq!(contract.get_pool_info(DEFAULT_POOL_ID).unwrap().fee_bps, 100);
+	}
+}
@@ -0,0 +1,199 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	json_types::U128,
+	near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId,
+};
+
+use crate::*;
+
+// LP position in the default pool, built from the reserves at each deposit. Withdrawals shrink
+// every field in proportion to the burned shares.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LpPosition {
+	pub shares: U128,
+	// Amounts of (token_a, token_b) the shares were worth when deposited, the hodl portfolio
+	pub deposited: Vec<U128>,
+	// Pool reserves of (token_a, token_b) after the last deposit
+	pub entry_reserves: Vec<U128>,
+	// Average value of a share in token_b at the deposits, scaled by PRICE_PRECISION
+	pub entry_share_price: U128,
+	// sqrt(reserve_a * reserve_b) of the shares when deposited. It only grows by fees.
+	pub entry_liquidity: U128,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LpPositionView {
+	pub position: LpPosition,
+	// Values below are in token_b at the current pool price
+	pub value_token_id: AccountId,
+	pub current_value: U128,
+	// Value of the deposited amounts if they had been held instead
+	pub hodl_value: U128,
+	// Value lost against holding, fees not included
+	pub impermanent_loss: U128,
+	pub fees_earned: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+	// P&L of the account's liquidity in the default pool, None for positions opened before
+	// the tracking or closed
+	#[handle_result]
+	pub fn get_lp_position(
+		&self,
+		account_id: AccountId,
+	) -> Result<Option<LpPositionView>, AmmError> {
+		let position = match self.lp_positions.get(&account_id) {
+			Some(position) => position,
+			None => return Ok(None),
+		};
+		let (reserve_a, reserve_b) = self.pool_reserves()?;
+		let (amount_a, amount_b) = self.shares_to_amounts(position.shares.0)?;
+		let current_value = value_in_token_b(amount_a, amount_b, reserve_a, reserve_b)?;
+		let hodl_value = value_in_token_b(
+			position.deposited[0].0,
+			position.deposited[1].0,
+			reserve_a,
+			reserve_b,
+		)?;
+		// Swaps keep the liquidity of a share constant apart from the fees they leave in the pool
+		let liquidity = self.shares_liquidity(position.shares.0)?;
+		let fees_earned = current_value -
+			mul_div(current_value, position.entry_liquidity.0.min(liquidity), liquidity)?;
+		Ok(Some(LpPositionView {
+			value_token_id: self.token_b_contract.clone(),
+			current_value: U128(current_value),
+			hodl_value: U128(hodl_value),
+			impermanent_loss: U128(hodl_value.saturating_sub(current_value - fees_earned)),
+			fees_earned: U128(fees_earned),
+			position,
+		}))
+	}
+}
+
+impl Contract {
+	// Adds freshly minted shares to the account's position, after the reserves include them
+	pub(crate) fn record_lp_deposit(
+		&mut self,
+		account_id: &AccountId,
+		shares: u128,
+	) -> Result<(), AmmError> {
+		let (reserve_a, reserve_b) = self.pool_reserves()?;
+		let (amount_a, amount_b) = self.shares_to_amounts(shares)?;
+		let value = value_in_token_b(amount_a, amount_b, reserve_a, reserve_b)?;
+		let liquidity = self.shares_liquidity(shares)?;
+		let mut position = self.lp_positions.get(account_id).unwrap_or(LpPosition {
+			shares: U128(0),
+			deposited: vec![U128(0); 2],
+			entry_reserves: vec![U128(0); 2],
+			entry_share_price: U128(0),
+			entry_liquidity: U128(0),
+		});
+		// Value of all the deposits at their share price
+		let entry_value =
+			mul_div(position.entry_share_price.0, position.shares.0, PRICE_PRECISION)?
+				.checked_add(value)
+				.ok_or(AmmError::MathOverflow)?;
+		position.shares.0 += shares;
+		position.deposited[0].0 += amount_a;
+		position.deposited[1].0 += amount_b;
+		position.entry_reserves = vec![U128(reserve_a), U128(reserve_b)];
+		position.entry_share_price =
+			U128(mul_div(entry_value, PRICE_PRECISION, position.shares.0)?);
+		position.entry_liquidity.0 += liquidity;
+		self.lp_positions.insert(account_id, &position);
+		Ok(())
+	}
+
+	// Removes burned shares from the account's position
+	pub(crate) fn record_lp_withdrawal(
+		&mut self,
+		account_id: &AccountId,
+		shares: u128,
+	) -> Result<(), AmmError> {
+		let mut position = match self.lp_positions.get(account_id) {
+			Some(position) => position,
+			None => return Ok(()),
+		};
+		if shares >= position.shares.0 {
+			self.lp_positions.remove(account_id);
+			return Ok(())
+		}
+		let left = position.shares.0 - shares;
+		for amount in position.deposited.iter_mut() {
+			amount.0 = mul_div(amount.0, left, position.shares.0)?;
+		}
+		position.entry_liquidity.0 = mul_div(position.entry_liquidity.0, left, position.shares.0)?;
+		position.shares.0 = left;
+		self.lp_positions.insert(account_id, &position);
+		Ok(())
+	}
+
+	// Part of sqrt(reserve_a * reserve_b) owned by the shares
+	fn shares_liquidity(&self, shares: u128) -> Result<u128, AmmError> {
+		let (reserve_a, reserve_b) = self.pool_reserves()?;
+		let root = (U256::from(reserve_a) * U256::from(reserve_b)).integer_sqrt().as_u128();
+		mul_div(root, shares, self.token_lp.total_supply)
+	}
+}
+
+fn value_in_token_b(
+	amount_a: u128,
+	amount_b: u128,
+	reserve_a: u128,
+	reserve_b: u128,
+) -> Result<u128, AmmError> {
+	mul_div(amount_a, reserve_b, reserve_a)?
+		.checked_add(amount_b)
+		.ok_or(AmmError::MathOverflow)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_lp_position() {
+		let (mut context, mut contract) = setup_pool();
+		contract.set_pool_fee(DEFAULT_POOL_ID, 30).unwrap();
+		let owner_id = contract.owner_id.clone();
+		let view = contract.get_lp_position(owner_id.clone()).unwrap().unwrap();
+		assert_eq!(view.position.deposited, vec![U128(POOL_A), U128(POOL_B)]);
+		assert_eq!(view.position.entry_reserves, vec![U128(POOL_A), U128(POOL_B)]);
+		let share_price = mul_div(2 * POOL_B, PRICE_PRECISION, contract.token_lp.total_supply);
+		assert_eq!(view.position.entry_share_price.0, share_price.unwrap());
+		assert_eq!(view.current_value, view.hodl_value);
+		assert_eq!(view.impermanent_loss, U128(0));
+		assert_eq!(view.fees_earned, U128(0));
+		assert_eq!(contract.get_lp_position(accounts(2)).unwrap(), None);
+
+		// A large trade moves the price and leaves a fee of 300 token_a in the pool
+		deposit(&mut contract, &token_a(), &accounts(2), 100_000);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		contract.swap(token_b(), token_a(), U128(100_000), None, None).unwrap();
+		let view = contract.get_lp_position(owner_id.clone()).unwrap().unwrap();
+		let (reserve_a, reserve_b) = contract.pool_reserves().unwrap();
+		assert_eq!(view.current_value, U128(2 * reserve_b));
+		let fee_value = mul_div(300, reserve_b, reserve_a).unwrap();
+		// Up to rounding of the square roots
+		assert!(view.fees_earned.0.abs_diff(fee_value) <= 3);
+		assert!(view.impermanent_loss.0 > 0);
+		assert_eq!(
+			view.current_value.0 + view.impermanent_loss.0,
+			view.hodl_value.0 + view.fees_earned.0
+		);
+
+		testing_env!(context.predecessor_account_id(owner_id.clone()).build());
+		contract.exclude_tokens_from_pool(token_a(), token_b(), None).unwrap();
+		assert_eq!(contract.get_lp_position(owner_id).unwrap(), None);
+	}
+}
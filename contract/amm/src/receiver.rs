@@ -0,0 +1,133 @@
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::{
+	env,
+	json_types::U128,
+	log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	serde_json, AccountId, PromiseOrValue,
+};
+
+use crate::*;
+
+// `msg` of ft_transfer_call, an empty msg deposits to the internal balance of the sender
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum TokenReceiverMessage {
+	// Funds the rewards of a farm
+	Farm { farm_id: u64 },
+	// Deposits to the sender and runs the actions as the sender
+	Actions(Vec<Action>),
+}
+
+// Use FT.ft_transfer_call to send tokens from FT to the AMM Pool. Rejected transfers don't
+// panic, the unused amount is returned so the token contract refunds it to the sender. Only a
+// failed batch of actions panics, the token contract refunds the failed call in full.
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+	fn ft_on_transfer(
+		&mut self,
+		sender_id: AccountId,
+		amount: U128,
+		msg: String,
+	) -> PromiseOrValue<U128> {
+		let token_id = env::predecessor_account_id();
+		match self.internal_on_transfer(&token_id, &sender_id, amount.0, &msg) {
+			Ok(unused) => PromiseOrValue::Value(U128(unused)),
+			Err(error) => {
+				log!("Refunding {} of {} to {}: {}", amount.0, token_id, sender_id, error);
+				PromiseOrValue::Value(amount)
+			},
+		}
+	}
+}
+
+impl Contract {
+	// Returns the unused amount. Nothing may change in state before an error is returned, since
+	// the call doesn't fail and wouldn't roll it back.
+	fn internal_on_transfer(
+		&mut self,
+		token_id: &AccountId,
+		sender_id: &AccountId,
+		amount: u128,
+		msg: &str,
+	) -> Result<u128, AmmError> {
+		if msg.is_empty() {
+			self.deposit_transfer(token_id, sender_id, amount)?;
+			self.assert_invariants_after_deposit();
+			return Ok(0)
+		}
+		let message: TokenReceiverMessage =
+			serde_json::from_str(msg).map_err(|_| AmmError::InvalidMessage)?;
+		match message {
+			TokenReceiverMessage::Farm { farm_id } =>
+				self.internal_fund_farm(farm_id, token_id, amount)?,
+			TokenReceiverMessage::Actions(actions) => {
+				self.deposit_transfer(token_id, sender_id, amount)?;
+				// The deposit already changed state, so a failed batch panics to revert it.
+				// The token contract refunds the transfer of a failed call.
+				self.internal_execute_actions(sender_id, actions, Some(U128(amount)))
+					.unwrap_or_else(|e| e.panic());
+				self.assert_invariants_after_deposit();
+			},
+		}
+		Ok(0)
+	}
+
+	// The sender is already credited, so a violation panics instead of returning a refund
+	fn assert_invariants_after_deposit(&self) {
+		self.assert_invariants().unwrap_or_else(|e| e.panic())
+	}
+
+	// Farm rewards can be any token, deposits only whitelisted ones
+	fn deposit_transfer(
+		&mut self,
+		token_id: &AccountId,
+		sender_id: &AccountId,
+		amount: u128,
+	) -> Result<(), AmmError> {
+		if !self.whitelisted_tokens.contains(token_id) {
+			return Err(AmmError::TokenNotSupported(token_id.clone()))
+		}
+		self.assert_not_paused(None, PauseAction::Deposits)?;
+		self.internal_deposit(token_id, sender_id, amount)
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	fn on_transfer(contract: &mut Contract, sender_id: AccountId, msg: &str) -> U128 {
+		match contract.ft_on_transfer(sender_id, U128(100), msg.to_string()) {
+			PromiseOrValue::Value(unused) => unused,
+			PromiseOrValue::Promise(_) => unreachable!(),
+		}
+	}
+
+	#[test]
+	fn test_rejected_deposits_are_refunded() {
+		let (mut context, mut contract) = setup_contract();
+		deposit(&mut contract, &token_a(), &accounts(2), 0);
+		testing_env!(context.predecessor_account_id(token_a()).build());
+
+		// The sender has no storage in the AMM
+		assert_eq!(on_transfer(&mut contract, accounts(5), ""), U128(100));
+		assert_eq!(on_transfer(&mut contract, accounts(2), "{\"unknown\": 1}"), U128(100));
+		assert_eq!(
+			on_transfer(&mut contract, accounts(2), "{\"farm\": {\"farm_id\": 7}}"),
+			U128(100)
+		);
+		assert_eq!(on_transfer(&mut contract, accounts(2), ""), U128(0));
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(100));
+
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		contract.pause(None, Some(vec![PauseAction::Deposits])).unwrap();
+		testing_env!(context.predecessor_account_id(token_a()).build());
+		assert_eq!(on_transfer(&mut contract, accounts(2), ""), U128(100));
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(100));
+	}
+}
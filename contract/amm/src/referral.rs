@@ -0,0 +1,176 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	collections::{LookupMap, UnorderedMap},
+	env,
+	json_types::U128,
+	log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId,
+};
+
+use crate::*;
+
+// Approved referrers, e.g. front-ends, which get a share of the swap fee of the swaps they bring
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Referrals {
+	// referral_id:share of the swap fee in bps
+	pub fee_shares: UnorderedMap<AccountId, u32>,
+	// (referral_id, token_id):earned amount
+	pub earnings: LookupMap<(AccountId, AccountId), u128>,
+}
+
+impl Referrals {
+	pub fn new() -> Self {
+		Self {
+			fee_shares: UnorderedMap::new(b"ref".to_vec()),
+			earnings: LookupMap::new(b"refearn".to_vec()),
+		}
+	}
+}
+
+impl Default for Referrals {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Referrer {
+	pub referral_id: AccountId,
+	pub fee_share_bps: u32,
+	// All time earnings per token
+	pub earnings: Vec<TokenAmount>,
+}
+
+#[near_bindgen]
+impl Contract {
+	// Approves the referrer or changes its share of the swap fee
+	#[handle_result]
+	pub fn set_referrer(
+		&mut self,
+		referral_id: AccountId,
+		fee_share_bps: u32,
+	) -> Result<(), AmmError> {
+		self.assert_role(Role::FeeManager)?;
+		if fee_share_bps as u128 > BPS_DENOMINATOR {
+			return Err(AmmError::InvalidFee(fee_share_bps))
+		}
+		self.referrals.fee_shares.insert(&referral_id, &fee_share_bps);
+		log!("Referrer {} gets {} bps of the swap fee", referral_id, fee_share_bps);
+		Ok(())
+	}
+
+	// Earnings stay in the internal balances of the referrer
+	#[handle_result]
+	pub fn remove_referrer(&mut self, referral_id: AccountId) -> Result<(), AmmError> {
+		self.assert_role(Role::FeeManager)?;
+		self.referrals.fee_shares.remove(&referral_id);
+		log!("Referrer {} removed", referral_id);
+		Ok(())
+	}
+
+	pub fn get_referrer(&self, referral_id: AccountId) -> Option<Referrer> {
+		let fee_share_bps = self.referrals.fee_shares.get(&referral_id)?;
+		Some(self.referrer(referral_id, fee_share_bps))
+	}
+
+	pub fn get_referrers(&self, from: Option<u64>, limit: Option<u64>) -> Vec<Referrer> {
+		self.referrals
+			.fee_shares
+			.iter()
+			.skip(from.unwrap_or(0) as usize)
+			.take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+			.map(|(referral_id, fee_share_bps)| self.referrer(referral_id, fee_share_bps))
+			.collect()
+	}
+}
+
+impl Contract {
+	fn referrer(&self, referral_id: AccountId, fee_share_bps: u32) -> Referrer {
+		let earnings = self
+			.token_ids()
+			.into_iter()
+			.map(|token_id| TokenAmount {
+				amount: U128(
+					self.referrals
+						.earnings
+						.get(&(referral_id.clone(), token_id.clone()))
+						.unwrap_or(0),
+				),
+				token_id,
+			})
+			.collect();
+		Referrer { referral_id, fee_share_bps, earnings }
+	}
+
+	// Pays the referrer's share of the swap fee out of the pool. Unknown referrers and referrers
+	// without storage in the token are skipped, so a wrong referral_id never fails a swap.
+	pub(crate) fn pay_referral_fee(
+		&mut self,
+		referral_id: &AccountId,
+		token_id: &AccountId,
+		fee: u128,
+	) -> Result<u128, AmmError> {
+		let fee_share_bps = match self.referrals.fee_shares.get(referral_id) {
+			Some(fee_share_bps) => fee_share_bps,
+			None => {
+				log!("Referrer {} is not approved", referral_id);
+				return Ok(0)
+			},
+		};
+		let mut token = self.get_token(token_id)?;
+		if !token.accounts.contains_key(referral_id) {
+			log!("Referrer {} is not registered in {}", referral_id, token_id);
+			return Ok(0)
+		}
+		let referral_fee = mul_div(fee, fee_share_bps as u128, BPS_DENOMINATOR)?;
+		if referral_fee == 0 {
+			return Ok(0)
+		}
+		token.internal_transfer(&env::current_account_id(), referral_id, referral_fee, None);
+		self.tokens.insert(token_id, &token);
+		self.update_ratio()?;
+		let key = (referral_id.clone(), token_id.clone());
+		let earned = self.referrals.earnings.get(&key).unwrap_or(0);
+		self.referrals.earnings.insert(&key, &(earned + referral_fee));
+		Ok(referral_fee)
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_referral_fee() {
+		let (mut context, mut contract) = setup_pool();
+		contract.set_pool_fee(DEFAULT_POOL_ID, 100).unwrap();
+		assert_eq!(contract.set_referrer(accounts(5), 20_000), Err(AmmError::InvalidFee(20_000)));
+		contract.set_referrer(accounts(5), 5_000).unwrap();
+		deposit(&mut contract, &token_b(), &accounts(5), 0);
+		deposit(&mut contract, &token_b(), &accounts(2), 20_000);
+		deposit(&mut contract, &token_a(), &accounts(2), 0);
+
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		contract
+			.swap(token_a(), token_b(), U128(10_000), Some(accounts(5)), None)
+			.unwrap();
+		// Half of the 100 fee
+		assert_eq!(contract.ft_balance_of(token_b(), accounts(5)).unwrap(), U128(50));
+		let (_, reserve_b) = contract.pool_reserves().unwrap();
+		assert_eq!(reserve_b, POOL_B + 10_000 - 50);
+
+		// Unknown referrers are ignored
+		contract
+			.swap(token_a(), token_b(), U128(10_000), Some(accounts(3)), None)
+			.unwrap();
+		let referrer = contract.get_referrer(accounts(5)).unwrap();
+		assert_eq!(referrer.earnings[1], TokenAmount { token_id: token_b(), amount: U128(50) });
+		assert_eq!(contract.get_referrers(None, None).len(), 1);
+	}
+}
@@ -0,0 +1,266 @@
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	env,
+	json_types::{U128, U64},
+	log, near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId, Gas, Promise, PromiseError,
+};
+
+use crate::*;
+
+// Real balance of the AMM account in a token contract, as returned by the last `sync`
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SyncedBalance {
+	pub balance: U128,
+	// Expected balance when the sync resolved
+	pub expected: U128,
+	pub timestamp: U64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenDrift {
+	pub token_id: AccountId,
+	// Internal balances plus escrowed amounts and transfers in flight, what the AMM should hold
+	pub expected: U128,
+	pub synced: Option<SyncedBalance>,
+	// Held at the last sync on top of expected, both then and now, e.g. tokens sent with a plain
+	// ft_transfer
+	pub surplus: U128,
+	pub deficit: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+	// Fetches the real balance of the AMM account in the token contract. Transfers in flight
+	// count as expected, whether the balance includes them yet or not.
+	#[handle_result]
+	pub fn sync(&mut self, token_id: AccountId) -> Result<Promise, AmmError> {
+		self.assert_owner()?;
+		self.get_token(&token_id)?;
+		Ok(ext_ft::ext(token_id.clone())
+			.with_static_gas(Gas(5 * TGAS))
+			.ft_balance_of(env::current_account_id())
+			.then(
+				ext_self::ext(env::current_account_id())
+					.with_static_gas(Gas(5 * TGAS))
+					.on_sync(token_id),
+			))
+	}
+
+	#[private]
+	pub fn on_sync(
+		&mut self,
+		token_id: AccountId,
+		#[callback_result] balance: Result<U128, PromiseError>,
+	) -> Option<TokenDrift> {
+		let balance = match balance {
+			Ok(balance) => balance,
+			Err(_) => {
+				log!("Failed to fetch the balance of {}", token_id);
+				return None
+			},
+		};
+		let expected = match self.expected_balance(&token_id) {
+			Ok(expected) => expected,
+			Err(_) => return None,
+		};
+		let synced = SyncedBalance {
+			balance,
+			expected: U128(expected),
+			timestamp: U64(env::block_timestamp()),
+		};
+		self.synced_balances.insert(&token_id, &synced);
+		let drift = self.token_drift(token_id).ok()?;
+		log!(
+			"Synced {}: expected {}, held {}",
+			drift.token_id,
+			drift.expected.0,
+			drift.synced.as_ref().map_or(0, |synced| synced.balance.0)
+		);
+		Some(drift)
+	}
+
+	// Credits the surplus found by the last `sync` to the internal balance of `to`
+	#[handle_result]
+	pub fn skim(&mut self, token_id: AccountId, to: AccountId) -> Result<U128, AmmError> {
+		self.assert_owner()?;
+		let drift = self.token_drift(token_id.clone())?;
+		if drift.surplus.0 == 0 {
+			return Err(AmmError::NoSurplus(token_id))
+		}
+		self.internal_deposit(&token_id, &to, drift.surplus.0)?;
+		// A skimmed sync can't be skimmed again
+		self.synced_balances.remove(&token_id);
+		log!("Skimmed {} of {} to {}", drift.surplus.0, token_id, to);
+		Ok(drift.surplus)
+	}
+
+	#[handle_result]
+	pub fn get_accounting_drift(&self) -> Result<Vec<TokenDrift>, AmmError> {
+		self.token_ids()
+			.into_iter()
+			.map(|token_id| self.token_drift(token_id))
+			.collect()
+	}
+}
+
+impl Contract {
+	fn token_drift(&self, token_id: AccountId) -> Result<TokenDrift, AmmError> {
+		let expected = self.expected_balance(&token_id)?;
+		let synced = self.synced_balances.get(&token_id);
+		let (held, expected_at_sync) = synced
+			.as_ref()
+			.map_or((expected, expected), |synced| (synced.balance.0, synced.expected.0));
+		// Withdrawals since the sync lower expected but not the synced balance, so they are
+		// no surplus
+		Ok(TokenDrift {
+			token_id,
+			expected: U128(expected),
+			synced,
+			surplus: U128(held.saturating_sub(expected.max(expected_at_sync))),
+			deficit: U128(expected.saturating_sub(held)),
+		})
+	}
+
+	// Amount of the token the AMM account should hold
+	pub(crate) fn expected_balance(&self, token_id: &AccountId) -> Result<u128, AmmError> {
+		let total_supply = self.get_token(token_id)?.total_supply;
+		total_supply
+			.checked_add(self.escrowed.get(token_id).unwrap_or(0))
+			.and_then(|total| total.checked_add(self.pending_transfers.get(token_id).unwrap_or(0)))
+			.ok_or(AmmError::MathOverflow)
+	}
+
+	// Records an amount moving between the AMM account and the ledgers, e.g. a withdrawal
+	// debited before its transfer resolved. Its callback removes it again.
+	pub(crate) fn add_pending_transfer(&mut self, token_id: &AccountId, amount: u128) {
+		let pending = self.pending_transfers.get(token_id).unwrap_or(0);
+		self.pending_transfers.insert(token_id, &(pending + amount));
+	}
+
+	pub(crate) fn sub_pending_transfer(&mut self, token_id: &AccountId, amount: u128) {
+		let pending = self.pending_transfers.get(token_id).unwrap_or(0) - amount;
+		if pending == 0 {
+			self.pending_transfers.remove(token_id);
+		} else {
+			self.pending_transfers.insert(token_id, &pending);
+		}
+	}
+
+	// Moves an amount from the internal balance of the account to escrow, e.g. for an order
+	pub(crate) fn internal_escrow(
+		&mut self,
+		token_id: &AccountId,
+		account_id: &AccountId,
+		amount: u128,
+	) -> Result<(), AmmError> {
+		let mut token = self.get_token(token_id)?;
+		if internal_balance_of(&token, account_id)? < amount {
+			return Err(AmmError::NotEnoughBalance)
+		}
+		token.internal_withdraw(account_id, amount);
+		self.tokens.insert(token_id, &token);
+		self.add_escrow(token_id, amount);
+		Ok(())
+	}
+
+	// Moves an escrowed amount back to the internal balance of the account
+	pub(crate) fn internal_release(
+		&mut self,
+		token_id: &AccountId,
+		account_id: &AccountId,
+		amount: u128,
+	) -> Result<(), AmmError> {
+		self.internal_deposit(token_id, account_id, amount)?;
+		self.sub_escrow(token_id, amount);
+		Ok(())
+	}
+
+	// Tokens held by the AMM outside of the internal balances
+	pub(crate) fn add_escrow(&mut self, token_id: &AccountId, amount: u128) {
+		let escrowed = self.escrowed.get(token_id).unwrap_or(0);
+		self.escrowed.insert(token_id, &(escrowed + amount));
+	}
+
+	pub(crate) fn sub_escrow(&mut self, token_id: &AccountId, amount: u128) {
+		let escrowed = self.escrowed.get(token_id).unwrap_or(0);
+		self.escrowed.insert(token_id, &(escrowed - amount));
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env, PromiseResult, RuntimeFeesConfig, VMConfig};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_skim_surplus() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 1_000);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+
+		// Escrowed amounts still count as held by the AMM
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		contract
+			.place_limit_order(
+				DEFAULT_POOL_ID,
+				token_a(),
+				token_b(),
+				U128(400),
+				U128(PRICE_PRECISION),
+			)
+			.unwrap();
+		assert_eq!(contract.expected_balance(&token_a()).unwrap(), POOL_A + 1_000);
+		assert_eq!(contract.skim(token_a(), accounts(2)), Err(AmmError::NotOwner));
+
+		// Somebody sent 50 with a plain ft_transfer
+		testing_env!(context.predecessor_account_id(accounts(0)).build());
+		contract.on_sync(token_a(), Ok(U128(POOL_A + 1_050)));
+		let drift = &contract.get_accounting_drift().unwrap()[0];
+		assert_eq!((drift.surplus, drift.deficit), (U128(50), U128(0)));
+
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		assert_eq!(contract.skim(token_a(), accounts(2)).unwrap(), U128(50));
+		assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(650));
+		assert_eq!(contract.skim(token_a(), accounts(2)), Err(AmmError::NoSurplus(token_a())));
+	}
+
+	#[test]
+	fn test_withdrawal_after_sync_is_no_surplus() {
+		let (mut context, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 1_000);
+		testing_env!(context.predecessor_account_id(accounts(0)).build());
+		contract.on_sync(token_a(), Ok(U128(POOL_A + 1_050)));
+
+		// The withdrawal is debited while the synced balance still holds it
+		testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+		contract.withdraw_tokens(token_a(), U128(400)).unwrap();
+		let drift = &contract.get_accounting_drift().unwrap()[0];
+		assert_eq!(drift.surplus, U128(50));
+		testing_env!(
+			context.predecessor_account_id(accounts(0)).build(),
+			VMConfig::test(),
+			RuntimeFeesConfig::test(),
+			Default::default(),
+			vec![PromiseResult::Successful(vec![])],
+		);
+		contract.withdraw_tokens_callback(accounts(2), token_a(), U128(400));
+
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		assert_eq!(contract.skim(token_a(), accounts(1)).unwrap(), U128(50));
+
+		// A sync while a withdrawal is in flight counts it as expected
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+		contract.withdraw_tokens(token_a(), U128(600)).unwrap();
+		testing_env!(context.predecessor_account_id(accounts(0)).build());
+		contract.on_sync(token_a(), Ok(U128(POOL_A + 650)));
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		assert_eq!(contract.skim(token_a(), accounts(1)), Err(AmmError::NoSurplus(token_a())));
+	}
+}
@@ -0,0 +1,80 @@
+use near_contract_standards::fungible_token::metadata::{FungibleTokenMetadata, FT_METADATA_SPEC};
+use near_sdk::{
+	test_utils::{accounts, VMContextBuilder},
+	testing_env, AccountId,
+};
+
+use crate::*;
+
+pub const POOL_A: u128 = 400_000;
+pub const POOL_B: u128 = 200_000;
+
+pub fn token_a() -> AccountId {
+	accounts(3)
+}
+
+pub fn token_b() -> AccountId {
+	accounts(4)
+}
+
+pub fn metadata(symbol: &str, decimals: u8) -> FungibleTokenMetadata {
+	FungibleTokenMetadata {
+		spec: FT_METADATA_SPEC.to_string(),
+		name: symbol.to_string(),
+		symbol: symbol.to_string(),
+		icon: None,
+		reference: None,
+		reference_hash: None,
+		decimals,
+	}
+}
+
+// Credits an internal balance, registering the account if needed
+pub fn deposit(
+	contract: &mut Contract,
+	token_id: &AccountId,
+	account_id: &AccountId,
+	amount: u128,
+) {
+	let mut token = contract.tokens.get(token_id).unwrap();
+	if !token.accounts.contains_key(account_id) {
+		token.internal_register_account(account_id);
+		contract.add_token_holder(token_id, account_id);
+	}
+	token.internal_deposit(account_id, amount);
+	contract.tokens.insert(token_id, &token);
+}
+
+// AMM `accounts(0)` owned by `accounts(1)` right after `new`, predecessor is the owner
+pub fn setup_new_contract() -> (VMContextBuilder, Contract) {
+	let mut context = VMContextBuilder::new();
+	context.current_account_id(accounts(0)).predecessor_account_id(accounts(1));
+	testing_env!(context.build());
+	let contract = Contract::new(accounts(1), token_a(), token_b());
+	(context, contract)
+}
+
+// AMM `accounts(0)` owned by `accounts(1)` with token metadata fetched, predecessor is the owner
+pub fn setup_contract() -> (VMContextBuilder, Contract) {
+	let mut context = VMContextBuilder::new();
+	context.current_account_id(accounts(0)).predecessor_account_id(accounts(0));
+	testing_env!(context.build());
+	let mut contract = Contract::new(accounts(1), token_a(), token_b());
+	contract.on_ft_metadata(token_a(), metadata("FTA", 4)).unwrap();
+	contract.on_ft_metadata(token_b(), metadata("FTB", 4)).unwrap();
+	deposit(&mut contract, &token_a(), &accounts(0), 0);
+	deposit(&mut contract, &token_b(), &accounts(0), 0);
+	testing_env!(context.predecessor_account_id(accounts(1)).build());
+	(context, contract)
+}
+
+// Same as `setup_contract` with POOL_A and POOL_B liquidity provided by the owner
+pub fn setup_pool() -> (VMContextBuilder, Contract) {
+	let (context, mut contract) = setup_contract();
+	deposit(&mut contract, &token_a(), &accounts(1), POOL_A);
+	deposit(&mut contract, &token_b(), &accounts(1), POOL_B);
+	contract
+		.add_tokens_to_pool(token_a(), U128(POOL_A), token_b(), U128(POOL_B), None)
+		.unwrap();
+	(context, contract)
+}
@@ -0,0 +1,288 @@
+use near_contract_standards::fungible_token::{metadata::FungibleTokenMetadata, FungibleToken};
+use near_sdk::{
+	borsh::{self, BorshDeserialize, BorshSerialize},
+	collections::{LookupMap, UnorderedMap, UnorderedSet, Vector},
+	env,
+	json_types::U128,
+	log, near_bindgen, AccountId, Gas, Promise,
+};
+
+use crate::*;
+
+// Version of the `Contract` layout. Before releasing a layout change, freeze the released
+// layout below as a new `ContractVx`, add it to `VersionedContract` and bump this number.
+pub const STATE_VERSION: u32 = 3;
+
+// Storage key of the state version, missing for the unversioned V1 state
+pub const STATE_VERSION_KEY: &[u8] = b"version";
+
+pub const GAS_FOR_MIGRATE: Gas = Gas(50 * TGAS);
+
+// Unversioned layout of the first release
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ContractV1 {
+	pub owner_id: AccountId,
+	pub tokens: LookupMap<AccountId, FungibleToken>,
+	pub token_metadatas: LookupMap<AccountId, FungibleTokenMetadata>,
+	pub token_lp: FungibleToken,
+	pub token_a_contract: AccountId,
+	pub token_b_contract: AccountId,
+	pub token_ratio: (U128, U128),
+}
+
+// Layout with roles, pause controls and the circuit breaker
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ContractV2 {
+	pub owner_id: AccountId,
+	pub tokens: LookupMap<AccountId, FungibleToken>,
+	pub token_metadatas: LookupMap<AccountId, FungibleTokenMetadata>,
+	pub token_lp: FungibleToken,
+	pub token_a_contract: AccountId,
+	pub token_b_contract: AccountId,
+	pub token_ratio: (U128, U128),
+	pub pending_owner_id: Option<AccountId>,
+	pub roles: UnorderedMap<AccountId, Vec<Role>>,
+	pub paused: PauseStatus,
+	pub paused_pools: LookupMap<u64, PauseStatus>,
+	pub circuit_breaker: Option<CircuitBreaker>,
+	pub price_oracle: PriceOracle,
+}
+
+// Every state layout the contract can find in storage
+pub enum VersionedContract {
+	V1(ContractV1),
+	V2(ContractV2),
+	V3(Box<Contract>),
+}
+
+impl VersionedContract {
+	pub fn read() -> Result<Self, AmmError> {
+		match read_state_version() {
+			1 => Ok(Self::V1(env::state_read().expect("Failed to read V1 state"))),
+			2 => Ok(Self::V2(env::state_read().expect("Failed to read V2 state"))),
+			STATE_VERSION =>
+				Ok(Self::V3(Box::new(env::state_read().expect("Failed to read state")))),
+			version => Err(AmmError::UnknownStateVersion(version)),
+		}
+	}
+}
+
+impl From<VersionedContract> for Contract {
+	fn from(state: VersionedContract) -> Self {
+		match state {
+			VersionedContract::V1(old) => ContractV2::from(old).into(),
+			VersionedContract::V2(old) => old.into(),
+			VersionedContract::V3(contract) => *contract,
+		}
+	}
+}
+
+impl From<ContractV1> for ContractV2 {
+	fn from(old: ContractV1) -> Self {
+		ContractV2 {
+			owner_id: old.owner_id,
+			tokens: old.tokens,
+			token_metadatas: old.token_metadatas,
+			token_lp: old.token_lp,
+			token_a_contract: old.token_a_contract,
+			token_b_contract: old.token_b_contract,
+			token_ratio: old.token_ratio,
+			pending_owner_id: None,
+			roles: UnorderedMap::new(b"roles".to_vec()),
+			paused: PauseStatus::default(),
+			paused_pools: LookupMap::new(b"paused".to_vec()),
+			circuit_breaker: None,
+			price_oracle: PriceOracle::default(),
+		}
+	}
+}
+
+// The price history moves into the default pool
+impl From<ContractV2> for Contract {
+	fn from(old: ContractV2) -> Self {
+		let mut whitelisted_tokens = UnorderedSet::new(b"wl".to_vec());
+		whitelisted_tokens.insert(&old.token_a_contract);
+		whitelisted_tokens.insert(&old.token_b_contract);
+		let mut pool = Pool::new(vec![old.token_a_contract.clone(), old.token_b_contract.clone()]);
+		pool.price_oracle = old.price_oracle;
+		let mut pools = Vector::new(b"pools".to_vec());
+		pools.push(&pool);
+		Contract {
+			owner_id: old.owner_id,
+			tokens: old.tokens,
+			token_metadatas: old.token_metadatas,
+			token_lp: old.token_lp,
+			token_a_contract: old.token_a_contract,
+			token_b_contract: old.token_b_contract,
+			token_ratio: old.token_ratio,
+			pending_owner_id: old.pending_owner_id,
+			roles: old.roles,
+			paused: old.paused,
+			paused_pools: old.paused_pools,
+			circuit_breaker: old.circuit_breaker,
+			pools,
+			wrap_near_id: None,
+			order_book: OrderBook::new(),
+			dca_orders: DcaOrders::new(),
+			farming: Farming::new(),
+			referrals: Referrals::new(),
+			whitelisted_tokens,
+			escrowed: LookupMap::new(b"escrow".to_vec()),
+			synced_balances: LookupMap::new(b"synced".to_vec()),
+			pending_transfers: LookupMap::new(b"pending".to_vec()),
+			intents: Intents::new(),
+			commitments: Commitments::new(),
+			batch_auctions: BatchAuctions::new(),
+			lp_positions: LookupMap::new(b"pos".to_vec()),
+			multi_pools: MultiPools::new(),
+			// Accounts registered before V3 can't be listed, so these ledgers are never
+			// reconciled and `check_invariants` reports them
+			token_holders: LookupMap::new(b"th".to_vec()),
+		}
+	}
+}
+
+pub(crate) fn read_state_version() -> u32 {
+	env::storage_read(STATE_VERSION_KEY)
+		.map(|bytes| u32::try_from_slice(&bytes).expect("Invalid state version"))
+		.unwrap_or(1)
+}
+
+pub(crate) fn write_state_version() {
+	env::storage_write(STATE_VERSION_KEY, &STATE_VERSION.try_to_vec().unwrap());
+}
+
+#[near_bindgen]
+impl Contract {
+	// Converts the stored state of any known version to the current layout.
+	// Called by `upgrade` right after the new code is deployed.
+	#[private]
+	#[init(ignore_state)]
+	#[handle_result]
+	pub fn migrate() -> Result<Self, AmmError> {
+		let version = read_state_version();
+		let contract = Contract::from(VersionedContract::read()?);
+		write_state_version();
+		log!("Migrated state from version {} to {}", version, STATE_VERSION);
+		Ok(contract)
+	}
+
+	// Deploys the new contract code passed as raw input and migrates the state in the same batch,
+	// so a failing migration reverts the deployment as well
+	#[handle_result]
+	pub fn upgrade(&self) -> Result<Promise, AmmError> {
+		self.assert_owner()?;
+		let code = env::input().ok_or(AmmError::NoContractCode)?;
+		Ok(Promise::new(env::current_account_id()).deploy_contract(code).function_call(
+			"migrate".to_string(),
+			vec![],
+			0,
+			GAS_FOR_MIGRATE,
+		))
+	}
+
+	pub fn get_state_version(&self) -> u32 {
+		read_state_version()
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{
+		test_utils::{accounts, VMContextBuilder},
+		testing_env,
+	};
+
+	use super::*;
+
+	#[test]
+	fn test_migrate_from_v1() {
+		let mut context = VMContextBuilder::new();
+		context.current_account_id(accounts(0)).predecessor_account_id(accounts(0));
+		testing_env!(context.build());
+
+		let mut token_lp = init_token(&accounts(1), b"lp".to_vec());
+		token_lp.internal_deposit(&accounts(1), 100);
+		let old = ContractV1 {
+			owner_id: accounts(1),
+			tokens: LookupMap::new(b"tok".to_vec()),
+			token_metadatas: LookupMap::new(b"tokdat".to_vec()),
+			token_lp,
+			token_a_contract: accounts(3),
+			token_b_contract: accounts(4),
+			token_ratio: (U128(40), U128(20)),
+		};
+		env::state_write(&old);
+		assert_eq!(read_state_version(), 1);
+
+		let contract = Contract::migrate().unwrap();
+		assert_eq!(read_state_version(), STATE_VERSION);
+		assert_eq!(contract.owner_id, accounts(1));
+		assert_eq!(contract.token_a_contract, accounts(3));
+		assert_eq!(contract.token_b_contract, accounts(4));
+		assert_eq!(contract.token_ratio, (U128(40), U128(20)));
+		assert_eq!(contract.token_lp.internal_unwrap_balance_of(&accounts(1)), 100);
+		assert_eq!(contract.pending_owner_id, None);
+		assert_eq!(contract.get_pause_status(None), PauseStatus::default());
+		assert_eq!(
+			contract.get_pool(DEFAULT_POOL_ID).unwrap().token_ids,
+			vec![accounts(3), accounts(4)]
+		);
+
+		// Migrating the current layout keeps the state untouched
+		env::state_write(&contract);
+		let contract = Contract::migrate().unwrap();
+		assert_eq!(contract.owner_id, accounts(1));
+		assert_eq!(contract.token_lp.total_supply, 100);
+	}
+
+	#[test]
+	fn test_migrate_from_v2() {
+		let mut context = VMContextBuilder::new();
+		context.current_account_id(accounts(0)).predecessor_account_id(accounts(0));
+		testing_env!(context.build());
+
+		let mut roles = UnorderedMap::new(b"roles".to_vec());
+		roles.insert(&accounts(2), &vec![Role::Guardian]);
+		let mut price_oracle = PriceOracle::default();
+		price_oracle.record(5, 2 * PRICE_PRECISION);
+		let observations = price_oracle.observations.clone();
+		let old = ContractV2 {
+			owner_id: accounts(1),
+			tokens: LookupMap::new(b"tok".to_vec()),
+			token_metadatas: LookupMap::new(b"tokdat".to_vec()),
+			token_lp: init_token(&accounts(1), b"lp".to_vec()),
+			token_a_contract: accounts(3),
+			token_b_contract: accounts(4),
+			token_ratio: (U128(40), U128(20)),
+			pending_owner_id: Some(accounts(5)),
+			roles,
+			paused: PauseStatus::default(),
+			paused_pools: LookupMap::new(b"paused".to_vec()),
+			circuit_breaker: Some(CircuitBreaker {
+				max_price_deviation_bps: 500,
+				twap_window_sec: 600,
+			}),
+			price_oracle,
+		};
+		env::state_write(&old);
+		write_v2_version();
+
+		let contract = Contract::migrate().unwrap();
+		assert_eq!(read_state_version(), STATE_VERSION);
+		assert_eq!(contract.pending_owner_id, Some(accounts(5)));
+		assert!(contract.has_role(Role::Guardian, accounts(2)));
+		assert!(contract.circuit_breaker.is_some());
+		assert_eq!(
+			contract.get_pool(DEFAULT_POOL_ID).unwrap().price_oracle.observations,
+			observations
+		);
+		assert_eq!(contract.get_whitelisted_tokens(), vec![accounts(3), accounts(4)]);
+		assert!(contract.get_multi_pools(None, None).is_empty());
+	}
+
+	fn write_v2_version() {
+		env::storage_write(STATE_VERSION_KEY, &2u32.try_to_vec().unwrap());
+	}
+}
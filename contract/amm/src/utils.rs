@@ -1,19 +1,152 @@
 use near_contract_standards::fungible_token::FungibleToken;
 use near_sdk::AccountId;
 
-pub fn add_decimals(value: u128, decimals: u8) -> u128 {
-	value * 10_u128.pow(decimals as u32)
+use crate::*;
+
+#[allow(clippy::all)]
+mod uint_types {
+	uint::construct_uint! {
+		pub struct U256(4);
+	}
+}
+pub use uint_types::U256;
+
+// Prices are fixed point numbers with 18 decimals
+pub const PRICE_PRECISION: u128 = 1_000_000_000_000_000_000;
+
+// Basis points denominator, 10_000 bps = 100%
+pub const BPS_DENOMINATOR: u128 = 10_000;
+
+pub fn add_decimals(value: u128, decimals: u8) -> Result<u128, AmmError> {
+	10_u128
+		.checked_pow(decimals as u32)
+		.and_then(|multiplier| value.checked_mul(multiplier))
+		.ok_or(AmmError::MathOverflow)
 }
 
-pub fn remove_decimals(value: u128, decimals: u8) -> u128 {
-	value / 10_u128.pow(decimals as u32)
+pub fn remove_decimals(value: u128, decimals: u8) -> Result<u128, AmmError> {
+	let divisor = 10_u128.checked_pow(decimals as u32).ok_or(AmmError::MathOverflow)?;
+	Ok(value / divisor)
 }
 
 // x*y = k
 // (x + dx)*(y - dy) = k
 // dy = y * dx / (x + dx)
-pub fn calc_dy(x: u128, y: u128, dx: u128) -> u128 {
-	y - (x * y / (x + dx))
+// The new reserve y - dy is rounded up, so k never decreases
+pub fn calc_dy(x: u128, y: u128, dx: u128) -> Result<u128, AmmError> {
+	if x == 0 || y == 0 {
+		return Err(AmmError::EmptyPool)
+	}
+	let x_plus_dx = x.checked_add(dx).ok_or(AmmError::MathOverflow)?;
+	Ok(y - mul_div_ceil(x, y, x_plus_dx)?)
+}
+
+// Part of `amount` to sell to a pool with reserve x of the token, so the rest and the output are
+// in the pool proportion after the swap. With the fee f taken from the input, solving
+// (amount - s) / (x + s) = dy / (y - dy) for s gives
+// s = (sqrt(((2 - f) * x)^2 + 4 * (1 - f) * x * amount) - (2 - f) * x) / (2 * (1 - f))
+pub fn calc_zap_swap_amount(x: u128, amount: u128, fee_bps: u32) -> Result<u128, AmmError> {
+	if x == 0 {
+		return Err(AmmError::EmptyPool)
+	}
+	let d = U256::from(BPS_DENOMINATOR);
+	let g = d - U256::from(fee_bps);
+	let b = (d + g) * U256::from(x);
+	let b_squared = b.checked_mul(b).ok_or(AmmError::MathOverflow)?;
+	let ac = (U256::from(4) * g * d * U256::from(x))
+		.checked_mul(U256::from(amount))
+		.ok_or(AmmError::MathOverflow)?;
+	let root = b_squared.checked_add(ac).ok_or(AmmError::MathOverflow)?.integer_sqrt();
+	Ok(((root - b) / (U256::from(2) * g)).as_u128())
+}
+
+// a * b / c without intermediate overflow
+pub fn mul_div(a: u128, b: u128, c: u128) -> Result<u128, AmmError> {
+	if c == 0 {
+		return Err(AmmError::DivisionByZero)
+	}
+	let result = U256::from(a) * U256::from(b) / U256::from(c);
+	if result > U256::from(u128::MAX) {
+		return Err(AmmError::MathOverflow)
+	}
+	Ok(result.as_u128())
+}
+
+// a * b / c rounded up
+pub fn mul_div_ceil(a: u128, b: u128, c: u128) -> Result<u128, AmmError> {
+	if c == 0 {
+		return Err(AmmError::DivisionByZero)
+	}
+	let result = (U256::from(a) * U256::from(b) + U256::from(c) - 1) / U256::from(c);
+	if result > U256::from(u128::MAX) {
+		return Err(AmmError::MathOverflow)
+	}
+	Ok(result.as_u128())
+}
+
+// Fee charged from the amount
+pub fn calc_fee(amount: u128, fee_bps: u32) -> Result<u128, AmmError> {
+	mul_div(amount, fee_bps as u128, BPS_DENOMINATOR)
+}
+
+// Price of token A denominated in token B
+pub fn calc_price(reserve_a: u128, reserve_b: u128) -> Result<u128, AmmError> {
+	mul_div(reserve_b, PRICE_PRECISION, reserve_a)
+}
+
+// Relative difference between two prices in basis points
+pub fn price_deviation_bps(price: u128, reference_price: u128) -> Result<u128, AmmError> {
+	mul_div(price.abs_diff(reference_price), BPS_DENOMINATOR, reference_price)
+}
+
+// The ed25519_verify host function of the NEAR runtime, which near-sdk 4 doesn't wrap yet
+#[cfg(target_arch = "wasm32")]
+pub fn ed25519_verify(signature: &[u8; 64], message: &[u8], public_key: &[u8; 32]) -> bool {
+	mod sys {
+		extern "C" {
+			pub fn ed25519_verify(
+				sig_len: u64,
+				sig_ptr: u64,
+				msg_len: u64,
+				msg_ptr: u64,
+				pub_key_len: u64,
+				pub_key_ptr: u64,
+			) -> u64;
+		}
+	}
+	unsafe {
+		sys::ed25519_verify(
+			signature.len() as u64,
+			signature.as_ptr() as u64,
+			message.len() as u64,
+			message.as_ptr() as u64,
+			public_key.len() as u64,
+			public_key.as_ptr() as u64,
+		) == 1
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn ed25519_verify(signature: &[u8; 64], message: &[u8], public_key: &[u8; 32]) -> bool {
+	use ed25519_dalek::Verifier;
+	match (
+		ed25519_dalek::Signature::from_bytes(signature),
+		ed25519_dalek::PublicKey::from_bytes(public_key),
+	) {
+		(Ok(signature), Ok(public_key)) => public_key.verify(message, &signature).is_ok(),
+		_ => false,
+	}
+}
+
+// Internal balance of a registered account
+pub fn internal_balance_of(
+	token: &FungibleToken,
+	account_id: &AccountId,
+) -> Result<u128, AmmError> {
+	token
+		.accounts
+		.get(account_id)
+		.ok_or_else(|| AmmError::AccountNotRegistered(account_id.clone()))
 }
 
 pub fn init_token(account_id: &AccountId, prefix: Vec<u8>) -> FungibleToken {
@@ -28,13 +161,14 @@ mod tests {
 	use super::*;
 	#[test]
 	fn test_add_decimals() {
-		let decimals = add_decimals(50, 3);
+		let decimals = add_decimals(50, 3).unwrap();
 		assert_eq!(decimals, 50_000);
+		assert_eq!(add_decimals(u128::MAX, 1), Err(AmmError::MathOverflow));
 	}
 
 	#[test]
 	fn test_remove_decimals() {
-		let decimals = remove_decimals(50000, 3);
+		let decimals = remove_decimals(50000, 3).unwrap();
 		assert_eq!(decimals, 50);
 	}
 
@@ -43,9 +177,35 @@ mod tests {
 		let x = 1_000_000; // 3 numbers float
 		let y = 40_000; // 1 number float
 		let max_decimals = 3;
-		let y = add_decimals(y, max_decimals - 1);
-		let dy = calc_dy(x, y, 1_000_000);
-		let dy = remove_decimals(dy, max_decimals - 1);
+		let y = add_decimals(y, max_decimals - 1).unwrap();
+		let dy = calc_dy(x, y, 1_000_000).unwrap();
+		let dy = remove_decimals(dy, max_decimals - 1).unwrap();
 		assert_eq!(dy, 20_000);
+		assert_eq!(calc_dy(0, y, 1_000_000), Err(AmmError::EmptyPool));
+		// 200_000 * 500 / 400_500 = 249.69
+		assert_eq!(calc_dy(400_000, 200_000, 500).unwrap(), 249);
+	}
+
+	#[test]
+	fn test_calc_zap_swap_amount() {
+		// Without a fee about half is sold, a bit more to make up for the price impact
+		let amount = calc_zap_swap_amount(1_000_000, 10_000, 0).unwrap();
+		assert_eq!(amount, 4_987);
+		let dy = calc_dy(1_000_000, 1_000_000, amount).unwrap();
+		// The rest and the output are in the new pool proportion, up to rounding
+		let (rest, output) = ((10_000 - amount) * (1_000_000 - dy), dy * (1_000_000 + amount));
+		assert!(price_deviation_bps(rest, output).unwrap() < 10);
+		assert!(calc_zap_swap_amount(1_000_000, 10_000, 30).unwrap() > amount);
+		assert_eq!(calc_zap_swap_amount(u128::MAX, 10_000, 30), Err(AmmError::MathOverflow));
+	}
+
+	#[test]
+	fn test_calc_price() {
+		let price = calc_price(u128::MAX / 4, u128::MAX / 2).unwrap();
+		assert_eq!(price, 2 * PRICE_PRECISION);
+		assert_eq!(price_deviation_bps(price, 2 * PRICE_PRECISION).unwrap(), 0);
+		assert_eq!(price_deviation_bps(PRICE_PRECISION, 2 * PRICE_PRECISION).unwrap(), 5_000);
+		assert_eq!(calc_price(0, 1), Err(AmmError::DivisionByZero));
+		assert_eq!(calc_price(1, u128::MAX), Err(AmmError::MathOverflow));
 	}
 }
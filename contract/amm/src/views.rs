@@ -0,0 +1,135 @@
+use near_contract_standards::storage_management::{StorageBalance, StorageManagement};
+use near_sdk::{
+	json_types::U128,
+	near_bindgen,
+	serde::{Deserialize, Serialize},
+	AccountId,
+};
+
+use crate::*;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenAmount {
+	pub token_id: AccountId,
+	pub amount: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenBalance {
+	pub token_id: AccountId,
+	pub balance: U128,
+	pub storage_balance: Option<StorageBalance>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolPosition {
+	pub pool_id: u64,
+	pub shares: U128,
+	pub total_shares: U128,
+	// Token amounts the shares can be exchanged for
+	pub amounts: Vec<TokenAmount>,
+	pub storage_balance: Option<StorageBalance>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountView {
+	pub account_id: AccountId,
+	// Internal balances of every supported token
+	pub balances: Vec<TokenBalance>,
+	// LP positions in pools the account is registered in
+	pub pools: Vec<PoolPosition>,
+	// Shares of multi-asset pools, their ids overlap with the ones of `pools`
+	pub multi_pools: Vec<PoolPosition>,
+}
+
+#[near_bindgen]
+impl Contract {
+	// Everything the account owns in the AMM in a single call
+	#[handle_result]
+	pub fn get_account(&self, account_id: AccountId) -> Result<AccountView, AmmError> {
+		let mut balances = vec![];
+		for token_id in self.token_ids() {
+			let token = self.get_token(&token_id)?;
+			balances.push(TokenBalance {
+				balance: U128(token.accounts.get(&account_id).unwrap_or(0)),
+				storage_balance: token.storage_balance_of(account_id.clone()),
+				token_id,
+			});
+		}
+
+		let mut pools = vec![];
+		if let Some(shares) = self.token_lp.accounts.get(&account_id) {
+			let (amount_a, amount_b) = self.shares_to_amounts(shares)?;
+			pools.push(PoolPosition {
+				pool_id: DEFAULT_POOL_ID,
+				shares: U128(shares),
+				total_shares: U128(self.token_lp.total_supply),
+				amounts: vec![
+					TokenAmount { token_id: self.token_a_contract.clone(), amount: U128(amount_a) },
+					TokenAmount { token_id: self.token_b_contract.clone(), amount: U128(amount_b) },
+				],
+				storage_balance: self.token_lp.storage_balance_of(account_id.clone()),
+			});
+		}
+
+		let mut multi_pools = vec![];
+		for pool in self.multi_pools.pools.iter() {
+			let shares = self.get_multi_pool_shares(pool.pool_id, account_id.clone()).0;
+			if shares == 0 {
+				continue
+			}
+			let mut amounts = vec![];
+			for (token_id, balance) in pool.token_ids.iter().zip(pool.balances.iter()) {
+				let amount = mul_div(balance.0, shares, pool.total_shares.0)?;
+				amounts.push(TokenAmount { token_id: token_id.clone(), amount: U128(amount) });
+			}
+			multi_pools.push(PoolPosition {
+				pool_id: pool.pool_id,
+				shares: U128(shares),
+				total_shares: pool.total_shares,
+				amounts,
+				storage_balance: None,
+			});
+		}
+
+		Ok(AccountView { account_id, balances, pools, multi_pools })
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::test_utils::accounts;
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_get_account() {
+		let (_, mut contract) = setup_pool();
+		deposit(&mut contract, &token_a(), &accounts(2), 500);
+
+		let account = contract.get_account(accounts(2)).unwrap();
+		assert_eq!(account.balances[0].balance, U128(500));
+		assert!(account.balances[0].storage_balance.is_some());
+		assert_eq!(account.balances[1].balance, U128(0));
+		assert!(account.balances[1].storage_balance.is_none());
+		assert!(account.pools.is_empty());
+
+		// The owner provided all the liquidity
+		let account = contract.get_account(accounts(1)).unwrap();
+		let position = &account.pools[0];
+		assert_eq!(position.shares, position.total_shares);
+		assert_eq!(
+			position.amounts,
+			vec![
+				TokenAmount { token_id: token_a(), amount: U128(POOL_A) },
+				TokenAmount { token_id: token_b(), amount: U128(POOL_B) },
+			]
+		);
+	}
+}
@@ -0,0 +1,111 @@
+use near_sdk::{env, log, near_bindgen, AccountId, Promise};
+
+use crate::*;
+
+// Storage prefix of the internal ledger of a registered token
+fn token_prefix(token_id: &AccountId) -> Vec<u8> {
+	[b"t:".as_slice(), token_id.as_bytes()].concat()
+}
+
+#[near_bindgen]
+impl Contract {
+	// Whitelists a NEP-141 contract, so it can be deposited and used in pools. The token can be
+	// traded once its metadata arrives in `on_ft_metadata`.
+	#[handle_result]
+	pub fn register_token(&mut self, token_id: AccountId) -> Result<Promise, AmmError> {
+		self.assert_role(Role::Admin)?;
+		if self.tokens.contains_key(&token_id) {
+			return Err(AmmError::TokenAlreadyRegistered(token_id))
+		}
+		let mut token = init_token(&self.owner_id, token_prefix(&token_id));
+		token.internal_register_account(&env::current_account_id());
+		self.tokens.insert(&token_id, &token);
+		self.token_holders.insert(
+			&token_id,
+			&holders_index(&token_id, &[&self.owner_id, &env::current_account_id()]),
+		);
+		self.whitelisted_tokens.insert(&token_id);
+		log!("Token {} registered", token_id);
+
+		Ok(ext_ft::ext(token_id.clone())
+			.ft_metadata()
+			.then(ext_self::ext(env::current_account_id()).on_ft_metadata(token_id)))
+	}
+
+	// Removes a token nobody holds and no pool uses from the whitelist. Escrowed amounts, e.g.
+	// of orders and farms, left the internal balances and count as held.
+	#[handle_result]
+	pub fn unregister_token(&mut self, token_id: AccountId) -> Result<(), AmmError> {
+		self.assert_role(Role::Admin)?;
+		let token = self.get_token(&token_id)?;
+		let in_pool = self.pools.iter().any(|pool| pool.token_ids.contains(&token_id)) ||
+			self.multi_pools.pools.iter().any(|pool| pool.token_ids.contains(&token_id));
+		let escrowed = self.escrowed.get(&token_id).unwrap_or(0);
+		if in_pool || token.total_supply > 0 || escrowed > 0 {
+			return Err(AmmError::TokenInUse(token_id))
+		}
+		self.tokens.remove(&token_id);
+		if let Some(mut holders) = self.token_holders.remove(&token_id) {
+			holders.clear();
+		}
+		self.token_metadatas.remove(&token_id);
+		self.whitelisted_tokens.remove(&token_id);
+		log!("Token {} unregistered", token_id);
+		Ok(())
+	}
+
+	pub fn get_whitelisted_tokens(&self) -> Vec<AccountId> {
+		self.whitelisted_tokens.to_vec()
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+	use near_sdk::{test_utils::accounts, testing_env, PromiseOrValue};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_register_token() {
+		let (mut context, mut contract) = setup_contract();
+		let token_c = accounts(5);
+		assert_eq!(
+			contract.register_token(token_a()).err(),
+			Some(AmmError::TokenAlreadyRegistered(token_a()))
+		);
+
+		// Deposits of unknown tokens are refunded
+		testing_env!(context.predecessor_account_id(token_c.clone()).build());
+		let refund = contract.ft_on_transfer(accounts(2), U128(100), "".to_string());
+		assert!(matches!(refund, PromiseOrValue::Value(U128(100))));
+
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		contract.register_token(token_c.clone()).unwrap();
+		assert_eq!(contract.get_whitelisted_tokens(), vec![token_a(), token_b(), token_c.clone()]);
+		testing_env!(context.predecessor_account_id(accounts(0)).build());
+		contract.on_ft_metadata(token_c.clone(), metadata("FTC", 6)).unwrap();
+
+		deposit(&mut contract, &token_c, &accounts(2), 0);
+		testing_env!(context.predecessor_account_id(token_c.clone()).build());
+		let refund = contract.ft_on_transfer(accounts(2), U128(100), "".to_string());
+		assert!(matches!(refund, PromiseOrValue::Value(U128(0))));
+
+		testing_env!(context.predecessor_account_id(accounts(1)).build());
+		assert_eq!(
+			contract.unregister_token(token_c.clone()),
+			Err(AmmError::TokenInUse(token_c.clone()))
+		);
+		assert_eq!(contract.unregister_token(token_a()), Err(AmmError::TokenInUse(token_a())));
+
+		// Balances moved to escrow still belong to someone
+		contract.internal_escrow(&token_c, &accounts(2), 100).unwrap();
+		assert_eq!(contract.get_token(&token_c).unwrap().total_supply, 0);
+		assert_eq!(
+			contract.unregister_token(token_c.clone()),
+			Err(AmmError::TokenInUse(token_c.clone()))
+		);
+	}
+}
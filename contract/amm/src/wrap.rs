@@ -0,0 +1,299 @@
+use near_sdk::{
+	assert_one_yocto, env, json_types::U128, log, near_bindgen, AccountId, Gas, Promise,
+	PromiseResult,
+};
+
+use crate::*;
+
+// Native NEAR is traded as wNEAR. Attached NEAR is wrapped first and credited to the internal
+// wNEAR balance once the wNEAR contract confirmed it, a failed wrap refunds the NEAR.
+#[near_bindgen]
+impl Contract {
+	#[handle_result]
+	pub fn set_wrap_near_id(&mut self, wrap_near_id: AccountId) -> Result<(), AmmError> {
+		self.assert_owner()?;
+		self.get_token(&wrap_near_id)?;
+		self.wrap_near_id = Some(wrap_near_id);
+		Ok(())
+	}
+
+	pub fn get_wrap_near_id(&self) -> Option<AccountId> {
+		self.wrap_near_id.clone()
+	}
+
+	// Wraps the attached NEAR and credits it to the internal wNEAR balance of the caller
+	#[payable]
+	#[handle_result]
+	pub fn deposit_near(&mut self) -> Result<Promise, AmmError> {
+		let account_id = env::predecessor_account_id();
+		let (wrap_near_id, amount, wrap) = self.wrap_attached_near(&account_id)?;
+		Ok(wrap.then(
+			ext_self::ext(env::current_account_id())
+				.with_static_gas(Gas(10 * TGAS))
+				.on_near_deposit(wrap_near_id, account_id, U128(amount)),
+		))
+	}
+
+	#[private]
+	pub fn on_near_deposit(
+		&mut self,
+		wrap_near_id: AccountId,
+		account_id: AccountId,
+		amount: U128,
+	) {
+		self.credit_wrapped_near(&wrap_near_id, &account_id, amount.0);
+	}
+
+	// Wraps the attached NEAR and sells it for `buy_token_id` in the default pool. If the swap
+	// fails once wrapped, the wNEAR stays in the internal balance of the caller.
+	#[payable]
+	#[handle_result]
+	pub fn swap_near(
+		&mut self,
+		buy_token_id: AccountId,
+		min_amount_out: U128,
+		referral_id: Option<AccountId>,
+	) -> Result<Promise, AmmError> {
+		self.assert_not_paused(Some(DEFAULT_POOL_ID), PauseAction::Swaps)?;
+		let account_id = env::predecessor_account_id();
+		internal_balance_of(&self.get_token(&buy_token_id)?, &account_id)?;
+		let (wrap_near_id, amount, wrap) = self.wrap_attached_near(&account_id)?;
+		Ok(wrap.then(
+			ext_self::ext(env::current_account_id())
+				.with_static_gas(Gas(30 * TGAS))
+				.on_near_swap(
+					wrap_near_id,
+					account_id,
+					U128(amount),
+					buy_token_id,
+					min_amount_out,
+					referral_id,
+				),
+		))
+	}
+
+	// Returns the amount bought, 0 if the swap didn't happen
+	#[private]
+	pub fn on_near_swap(
+		&mut self,
+		wrap_near_id: AccountId,
+		account_id: AccountId,
+		amount: U128,
+		buy_token_id: AccountId,
+		min_amount_out: U128,
+		referral_id: Option<AccountId>,
+	) -> U128 {
+		if !self.credit_wrapped_near(&wrap_near_id, &account_id, amount.0) {
+			return U128(0)
+		}
+		// Nothing is swapped on an error, so the callback must not panic and lose the credit
+		let result = self
+			.quote_swap(DEFAULT_POOL_ID, &wrap_near_id, &buy_token_id, amount.0)
+			.and_then(|(amount_out, _)| {
+				if amount_out < min_amount_out.0 {
+					return Err(AmmError::SlippageExceeded)
+				}
+				self.internal_swap_tokens(
+					&account_id,
+					DEFAULT_POOL_ID,
+					buy_token_id,
+					wrap_near_id,
+					amount,
+					referral_id,
+				)
+			});
+		result.unwrap_or_else(|e| {
+			log!("Swapping wrapped NEAR failed: {}", e);
+			U128(0)
+		})
+	}
+
+	// Unwraps wNEAR from the internal balance of the caller and sends it as native NEAR
+	#[payable]
+	#[handle_result]
+	pub fn withdraw_near(&mut self, amount: U128) -> Result<Promise, AmmError> {
+		assert_one_yocto();
+		self.assert_not_paused(None, PauseAction::Withdrawals)?;
+		let wrap_near_id = self.wrap_near_id.clone().ok_or(AmmError::WrapNearNotConfigured)?;
+		if amount.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		let account_id = env::predecessor_account_id();
+		let mut token = self.get_token(&wrap_near_id)?;
+		if internal_balance_of(&token, &account_id)? < amount.0 {
+			return Err(AmmError::NotEnoughBalance)
+		}
+		// Debit first, the callback restores the balance if unwrapping fails
+		token.internal_withdraw(&account_id, amount.0);
+		self.tokens.insert(&wrap_near_id, &token);
+		self.add_pending_transfer(&wrap_near_id, amount.0);
+
+		Ok(ext_wrap_near::ext(wrap_near_id.clone())
+			.with_attached_deposit(1)
+			.with_static_gas(Gas(5 * TGAS))
+			.near_withdraw(amount)
+			.then(
+				ext_self::ext(env::current_account_id())
+					.with_static_gas(Gas(10 * TGAS))
+					.on_near_withdraw(wrap_near_id, account_id, amount),
+			))
+	}
+
+	// The token id is passed along, `set_wrap_near_id` may change it while the call is in flight
+	#[private]
+	pub fn on_near_withdraw(
+		&mut self,
+		wrap_near_id: AccountId,
+		account_id: AccountId,
+		amount: U128,
+	) {
+		self.sub_pending_transfer(&wrap_near_id, amount.0);
+		match env::promise_result(0) {
+			PromiseResult::NotReady => unreachable!(),
+			PromiseResult::Successful(_) => {
+				Promise::new(account_id).transfer(amount.0);
+			},
+			PromiseResult::Failed => {
+				let mut token = self.get_token(&wrap_near_id).unwrap_or_else(|e| e.panic());
+				token.internal_deposit(&account_id, amount.0);
+				self.tokens.insert(&wrap_near_id, &token);
+				log!("Unwrapping {} wNEAR failed, refunded to {}", amount.0, account_id);
+			},
+		}
+	}
+}
+
+impl Contract {
+	// Starts wrapping the attached NEAR for the account, the caller chains the callback
+	fn wrap_attached_near(
+		&mut self,
+		account_id: &AccountId,
+	) -> Result<(AccountId, u128, Promise), AmmError> {
+		self.assert_not_paused(None, PauseAction::Deposits)?;
+		let wrap_near_id = self.wrap_near_id.clone().ok_or(AmmError::WrapNearNotConfigured)?;
+		let amount = env::attached_deposit();
+		if amount == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		internal_balance_of(&self.get_token(&wrap_near_id)?, account_id)?;
+		self.add_pending_transfer(&wrap_near_id, amount);
+
+		let wrap = ext_wrap_near::ext(wrap_near_id.clone())
+			.with_attached_deposit(amount)
+			.with_static_gas(Gas(5 * TGAS))
+			.near_deposit();
+		Ok((wrap_near_id, amount, wrap))
+	}
+
+	// Returns true if the NEAR was wrapped and credited, a failed wrap refunds it
+	fn credit_wrapped_near(
+		&mut self,
+		wrap_near_id: &AccountId,
+		account_id: &AccountId,
+		amount: u128,
+	) -> bool {
+		self.sub_pending_transfer(wrap_near_id, amount);
+		match env::promise_result(0) {
+			PromiseResult::NotReady => unreachable!(),
+			PromiseResult::Successful(_) => {
+				let mut token = self.get_token(wrap_near_id).unwrap_or_else(|e| e.panic());
+				token.internal_deposit(account_id, amount);
+				self.tokens.insert(wrap_near_id, &token);
+				log!("Deposited {} NEAR as wNEAR to {}", amount, account_id);
+				true
+			},
+			// The wNEAR contract refunded the NEAR to the AMM
+			PromiseResult::Failed => {
+				Promise::new(account_id.clone()).transfer(amount);
+				log!("Wrapping {} NEAR failed, refunded to {}", amount, account_id);
+				false
+			},
+		}
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env, RuntimeFeesConfig, VMConfig};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_deposit_and_withdraw_near() {
+		let (mut context, mut contract) = setup_new_contract();
+		assert!(matches!(contract.deposit_near(), Err(AmmError::WrapNearNotConfigured)));
+		contract.set_wrap_near_id(accounts(3)).unwrap();
+
+		deposit(&mut contract, &accounts(3), &accounts(2), 0);
+
+		// Credited once wrapped, a failed wrap credits nothing
+		for (result, balance) in
+			[(PromiseResult::Failed, 0), (PromiseResult::Successful(vec![]), 1_000)]
+		{
+			testing_env!(context
+				.predecessor_account_id(accounts(2))
+				.attached_deposit(1_000)
+				.build());
+			contract.deposit_near().unwrap();
+			assert_eq!(contract.pending_transfers.get(&accounts(3)), Some(1_000));
+			testing_env!(
+				context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+				VMConfig::test(),
+				RuntimeFeesConfig::test(),
+				Default::default(),
+				vec![result],
+			);
+			contract.on_near_deposit(accounts(3), accounts(2), U128(1_000));
+			assert_eq!(contract.ft_balance_of(accounts(3), accounts(2)).unwrap(), U128(balance));
+		}
+		testing_env!(context.predecessor_account_id(accounts(2)).build());
+
+		testing_env!(context.attached_deposit(1).build());
+		assert!(matches!(contract.withdraw_near(U128(2_000)), Err(AmmError::NotEnoughBalance)));
+		contract.withdraw_near(U128(400)).unwrap();
+		assert_eq!(contract.ft_balance_of(accounts(3), accounts(2)).unwrap(), U128(600));
+	}
+
+	#[test]
+	fn test_swap_near() {
+		let (mut context, mut contract) = setup_pool();
+		contract.set_wrap_near_id(token_a()).unwrap();
+		deposit(&mut contract, &token_a(), &accounts(2), 0);
+		deposit(&mut contract, &token_b(), &accounts(2), 0);
+		let amount_out = calc_dy(POOL_A, POOL_B, 1_000).unwrap();
+
+		// A failed wrap refunds, a swap below min_amount_out keeps the wNEAR
+		for (result, min_amount_out, balances) in [
+			(PromiseResult::Failed, 0, (0, 0)),
+			(PromiseResult::Successful(vec![]), amount_out + 1, (1_000, 0)),
+			(PromiseResult::Successful(vec![]), amount_out, (1_000, amount_out)),
+		] {
+			testing_env!(context
+				.predecessor_account_id(accounts(2))
+				.attached_deposit(1_000)
+				.build());
+			contract.swap_near(token_b(), U128(min_amount_out), None).unwrap();
+			testing_env!(
+				context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+				VMConfig::test(),
+				RuntimeFeesConfig::test(),
+				Default::default(),
+				vec![result],
+			);
+			let bought = contract.on_near_swap(
+				token_a(),
+				accounts(2),
+				U128(1_000),
+				token_b(),
+				U128(min_amount_out),
+				None,
+			);
+			assert_eq!(bought, U128(balances.1));
+			assert_eq!(contract.ft_balance_of(token_a(), accounts(2)).unwrap(), U128(balances.0));
+			assert_eq!(contract.ft_balance_of(token_b(), accounts(2)).unwrap(), U128(balances.1));
+		}
+		assert_eq!(contract.pending_transfers.get(&token_a()), None);
+	}
+}
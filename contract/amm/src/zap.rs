@@ -0,0 +1,206 @@
+use near_sdk::{env, json_types::U128, log, near_bindgen, AccountId};
+
+use crate::*;
+
+// Single-sided liquidity of the default pool for any account registered in the LP token
+#[near_bindgen]
+impl Contract {
+	// Sells the part of `amount` that balances the rest against the pool and adds both as
+	// liquidity. Rounding dust of the larger side stays in the pool.
+	#[handle_result]
+	pub fn zap_in(
+		&mut self,
+		token_id: AccountId,
+		amount: U128,
+		min_shares: U128,
+	) -> Result<U128, AmmError> {
+		let account_id = env::predecessor_account_id();
+		self.assert_not_paused(Some(DEFAULT_POOL_ID), PauseAction::Deposits)?;
+		self.assert_not_paused(Some(DEFAULT_POOL_ID), PauseAction::Swaps)?;
+		let other_id = self.other_pool_token(&token_id)?;
+		if amount.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		internal_balance_of(&self.token_lp, &account_id)?;
+		if internal_balance_of(&self.get_token(&token_id)?, &account_id)? < amount.0 {
+			return Err(AmmError::NotEnoughBalance)
+		}
+
+		let pool_owner_id = env::current_account_id();
+		let reserve_in = internal_balance_of(&self.get_token(&token_id)?, &pool_owner_id)?;
+		let reserve_out = internal_balance_of(&self.get_token(&other_id)?, &pool_owner_id)?;
+		let fee_bps = self.get_pool(DEFAULT_POOL_ID)?.effective_fee_bps(env::block_timestamp());
+		let swap_amount = calc_zap_swap_amount(reserve_in, amount.0, fee_bps)?;
+		let (amount_out, _) =
+			self.quote_swap(DEFAULT_POOL_ID, &token_id, &other_id, swap_amount)?;
+
+		// Shares of the rest and the output at the reserves after the swap
+		let rest = amount.0 - swap_amount;
+		let total_shares = self.token_lp.total_supply;
+		let shares = mul_div(total_shares, rest, reserve_in + swap_amount)?.min(mul_div(
+			total_shares,
+			amount_out,
+			reserve_out - amount_out,
+		)?);
+		if shares < min_shares.0 {
+			return Err(AmmError::SlippageExceeded)
+		}
+		if self
+			.internal_swap(DEFAULT_POOL_ID, &token_id, &other_id, swap_amount)?
+			.is_none()
+		{
+			return Err(AmmError::Paused(PauseAction::Swaps))
+		}
+
+		// The swap credited the pool with the sold part, the rest and the output go back as
+		// liquidity
+		let mut token = self.get_token(&token_id)?;
+		token.internal_withdraw(&account_id, swap_amount);
+		token.internal_transfer(&account_id, &pool_owner_id, rest, None);
+		self.tokens.insert(&token_id, &token);
+		let mut other = self.get_token(&other_id)?;
+		other.internal_deposit(&pool_owner_id, amount_out);
+		self.tokens.insert(&other_id, &other);
+		self.token_lp.internal_deposit(&account_id, shares);
+		self.record_lp_deposit(&account_id, shares)?;
+		log!("Zapped {} of {} into {} shares for {}", amount.0, token_id, shares, account_id);
+
+		self.update_ratio()?;
+		self.match_limit_orders(DEFAULT_POOL_ID, &other_id)?;
+		self.assert_invariants()?;
+		Ok(U128(shares))
+	}
+
+	// Removes the liquidity of `shares` and sells the other token of the pair for token_id
+	#[handle_result]
+	pub fn zap_out(
+		&mut self,
+		shares: U128,
+		token_id: AccountId,
+		min_amount_out: U128,
+	) -> Result<U128, AmmError> {
+		let account_id = env::predecessor_account_id();
+		self.assert_not_paused(Some(DEFAULT_POOL_ID), PauseAction::Withdrawals)?;
+		self.assert_not_paused(Some(DEFAULT_POOL_ID), PauseAction::Swaps)?;
+		let other_id = self.other_pool_token(&token_id)?;
+		if shares.0 == 0 {
+			return Err(AmmError::ZeroAmount)
+		}
+		if internal_balance_of(&self.token_lp, &account_id)? < shares.0 {
+			return Err(AmmError::NotEnoughBalance)
+		}
+
+		let (amount_a, amount_b) = self.shares_to_amounts(shares.0)?;
+		let (amount, other_amount) = if token_id == self.token_a_contract {
+			(amount_a, amount_b)
+		} else {
+			(amount_b, amount_a)
+		};
+		let pool_owner_id = env::current_account_id();
+		self.token_lp.internal_withdraw(&account_id, shares.0);
+		self.record_lp_withdrawal(&account_id, shares.0)?;
+		let mut token = self.get_token(&token_id)?;
+		token.internal_transfer(&pool_owner_id, &account_id, amount, None);
+		self.tokens.insert(&token_id, &token);
+		// The other token leaves the pool and is sold back to it at the reduced reserves
+		let mut other = self.get_token(&other_id)?;
+		other.internal_withdraw(&pool_owner_id, other_amount);
+		self.tokens.insert(&other_id, &other);
+		let swapped =
+			match self.internal_swap(DEFAULT_POOL_ID, &other_id, &token_id, other_amount)? {
+				Some((amount_out, _)) => amount_out,
+				None => return Err(AmmError::Paused(PauseAction::Swaps)),
+			};
+		let mut token = self.get_token(&token_id)?;
+		token.internal_deposit(&account_id, swapped);
+		self.tokens.insert(&token_id, &token);
+
+		let amount_out = amount + swapped;
+		if amount_out < min_amount_out.0 {
+			return Err(AmmError::SlippageExceeded)
+		}
+		log!("Zapped {} shares out to {} of {} for {}", shares.0, amount_out, token_id, account_id);
+		self.match_limit_orders(DEFAULT_POOL_ID, &token_id)?;
+		self.assert_invariants()?;
+		Ok(U128(amount_out))
+	}
+}
+
+impl Contract {
+	// The other token of the default pool
+	fn other_pool_token(&self, token_id: &AccountId) -> Result<AccountId, AmmError> {
+		if *token_id == self.token_a_contract {
+			Ok(self.token_b_contract.clone())
+		} else if *token_id == self.token_b_contract {
+			Ok(self.token_a_contract.clone())
+		} else {
+			Err(AmmError::TokenNotSupported(token_id.clone()))
+		}
+	}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+	use near_sdk::{test_utils::accounts, testing_env};
+
+	use super::*;
+	use crate::test_utils::*;
+
+	#[test]
+	fn test_zap_in_and_out() {
+		let (mut context, mut contract) = setup_pool();
+		contract.set_pool_fee(DEFAULT_POOL_ID, 30).unwrap();
+		let lp_id = accounts(2);
+		deposit(&mut contract, &token_a(), &lp_id, 10_000);
+		contract.token_lp.internal_register_account(&lp_id);
+		contract.add_token_holder(&env::current_account_id(), &lp_id);
+		testing_env!(context.predecessor_account_id(lp_id.clone()).build());
+		let total_shares = contract.token_lp.total_supply;
+
+		assert_eq!(
+			contract.zap_in(token_a(), U128(10_000), U128(total_shares)),
+			Err(AmmError::SlippageExceeded)
+		);
+		let shares = contract.zap_in(token_a(), U128(10_000), U128(0)).unwrap().0;
+		assert_eq!(contract.ft_balance_of(token_a(), lp_id.clone()).unwrap(), U128(0));
+		// Half of the value of both reserves, a bit less for the fee and the dust
+		assert!(shares > total_shares / 81 && shares < total_shares / 80);
+
+		// Swapping half back pays the fee and the price impact twice
+		let amount_out = contract.zap_out(U128(shares), token_a(), U128(0)).unwrap().0;
+		assert!(amount_out > 9_900 && amount_out < 10_000);
+		assert_eq!(contract.ft_balance_of(token_a(), lp_id).unwrap(), U128(amount_out));
+		assert_eq!(contract.token_lp.total_supply, total_shares);
+	}
+
+	#[test]
+	fn test_zap_trips_circuit_breaker() {
+		let (mut context, mut contract) = setup_pool();
+		let lp_id = accounts(2);
+		deposit(&mut contract, &token_a(), &lp_id, 200_000);
+		contract.token_lp.internal_register_account(&lp_id);
+		contract.add_token_holder(&env::current_account_id(), &lp_id);
+		contract
+			.set_circuit_breaker(Some(CircuitBreaker {
+				max_price_deviation_bps: 1_000,
+				twap_window_sec: 60,
+			}))
+			.unwrap();
+		let mut pool = contract.get_pool(DEFAULT_POOL_ID).unwrap();
+		pool.price_oracle.record(0, calc_price(POOL_A, POOL_B).unwrap());
+		contract.pools.replace(DEFAULT_POOL_ID, &pool);
+		testing_env!(context
+			.predecessor_account_id(lp_id.clone())
+			.block_timestamp(60 * NANOS_PER_SECOND)
+			.build());
+
+		// Fails like zap_out, nothing is credited
+		assert_eq!(
+			contract.zap_in(token_a(), U128(200_000), U128(0)),
+			Err(AmmError::Paused(PauseAction::Swaps))
+		);
+		assert_eq!(contract.ft_balance_of(token_a(), lp_id.clone()).unwrap(), U128(200_000));
+		assert_eq!(contract.token_lp.internal_unwrap_balance_of(&lp_id), 0);
+	}
+}
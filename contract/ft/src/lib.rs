@@ -3,16 +3,15 @@
 //! - The maximum balance value is limited by U128 (2**128 - 1).
 //! - JSON calls should pass U128 as a base-10 string. E.g. "100".
 //! - The contract optimizes the inner trie structure by hashing account IDs. It will prevent some
-//! abuse of deep tries. Shouldn't be an issue, once NEAR clients implement full hashing of keys.
+//!   abuse of deep tries. Shouldn't be an issue, once NEAR clients implement full hashing of keys.
 //! - The contract tracks the change in storage before and after the call. If the storage increases,
-//! the contract requires the caller of the contract to attach enough deposit to the function call
-//! to cover the storage cost.
-//! This is done to prevent a denial of service attack on the contract by taking all available
-//! storage. If the storage decreases, the contract will issue a refund for the cost of the released
-//! storage. The unused tokens from the attached deposit are also refunded, so it's safe to
-//! attach more deposit than required.
+//!   the contract requires the caller of the contract to attach enough deposit to the function call
+//!   to cover the storage cost. This is done to prevent a denial of service attack on the contract
+//!   by taking all available storage. If the storage decreases, the contract will issue a refund
+//!   for the cost of the released storage. The unused tokens from the attached deposit are also
+//!   refunded, so it's safe to attach more deposit than required.
 //! - To prevent the deployed contract from being modified or deleted, it should not have any access
-//! keys on its account.
+//!   keys on its account.
 use near_contract_standards::fungible_token::{
 	metadata::{FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC},
 	FungibleToken,
@@ -117,7 +116,7 @@ mod tests {
 	fn test_new() {
 		let mut context = get_context(accounts(1));
 		testing_env!(context.build());
-		let contract = Contract::new_default_meta(accounts(1).into(), TOTAL_SUPPLY.into());
+		let contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
 		testing_env!(context.is_view(true).build());
 		assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
 		assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY);
@@ -135,7 +134,7 @@ mod tests {
 	fn test_transfer() {
 		let mut context = get_context(accounts(2));
 		testing_env!(context.build());
-		let mut contract = Contract::new_default_meta(accounts(2).into(), TOTAL_SUPPLY.into());
+		let mut contract = Contract::new_default_meta(accounts(2), TOTAL_SUPPLY.into());
 		testing_env!(context
 			.storage_usage(env::storage_usage())
 			.attached_deposit(contract.storage_balance_bounds().min.into())
@@ -121,3 +121,6 @@ near view amm.$MASTER_ACCOUNT contract_info
 
 near view amm.$MASTER_ACCOUNT ft_balance_of '{"token_name": "fta.'$MASTER_ACCOUNT'", "account_id": "alice.'$MASTER_ACCOUNT'"}'
 near view amm.$MASTER_ACCOUNT ft_balance_of '{"token_name": "ftb.'$MASTER_ACCOUNT'", "account_id": "alice.'$MASTER_ACCOUNT'"}'
+
+near view amm.$MASTER_ACCOUNT get_account '{"account_id": "alice.'$MASTER_ACCOUNT'"}'
+near view amm.$MASTER_ACCOUNT get_pool_stats '{"pool_id": 0}'
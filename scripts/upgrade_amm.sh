@@ -0,0 +1,6 @@
+#!/bin/sh
+
+set -e
+
+# Deploy res/amm.wasm through the owner gated upgrade method, which migrates the state as well
+near call amm.$MASTER_ACCOUNT upgrade "$(base64 < res/amm.wasm | tr -d '\n')" --base64 --accountId alice.$MASTER_ACCOUNT --gas 300000000000000